@@ -0,0 +1,116 @@
+//! Counts the allocations and measures the time of matching `a*b*c*` over
+//! inputs of growing length, both with the NFA and by backtracking to find
+//! the captures, of failing to match `a.*a.*a.*a.*b` and `(a|aa)+b`, which
+//! would backtrack exponentially, and of finding `error:` in a log, where
+//! every offset that is tried allocates. Run with
+//! `cargo bench --bench allocations`.
+
+use regexp::Regexp;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let expr = Regexp::new("a*b*c*").unwrap();
+
+    for len in [1_000, 10_000, 100_000] {
+        let text = "a".repeat(len) + &"b".repeat(len) + &"c".repeat(len);
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        assert!(expr.matches(&text));
+
+        println!(
+            "matches over {} chars: {} allocations in {:?}",
+            text.len(),
+            ALLOCATIONS.load(Ordering::Relaxed) - before,
+            start.elapsed()
+        );
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        assert_eq!(expr.find_range(&text), Some(0..text.len()));
+
+        println!(
+            "find_range over {} chars: {} allocations in {:?}",
+            text.len(),
+            ALLOCATIONS.load(Ordering::Relaxed) - before,
+            start.elapsed()
+        );
+    }
+
+    let expr = Regexp::new("a.*a.*a.*a.*b").unwrap();
+
+    for len in [1_000, 10_000] {
+        let text = "a".repeat(len);
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        assert!(!expr.matches(&text));
+
+        println!(
+            "a.*a.*a.*a.*b fails over {} chars: {} allocations in {:?}",
+            text.len(),
+            ALLOCATIONS.load(Ordering::Relaxed) - before,
+            start.elapsed()
+        );
+    }
+
+    // Left to backtracking by the captures, with failed repeats memoized.
+    let expr = Regexp::new("(a|aa)+b").unwrap();
+
+    for len in [100, 1_000] {
+        let text = "a".repeat(len);
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        assert!(expr.captures(&text).is_none());
+
+        println!(
+            "(a|aa)+b fails over {} chars: {} allocations in {:?}",
+            text.len(),
+            ALLOCATIONS.load(Ordering::Relaxed) - before,
+            start.elapsed()
+        );
+    }
+
+    let log = "info: request handled in 3ms\n".repeat(10_000) + "error: disk full\n";
+
+    // `[e]rror:` matches the same, but has no literal prefix to scan for.
+    for pattern in ["error:", "[e]rror:"] {
+        let expr = Regexp::new(pattern).unwrap();
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        assert!(expr.find(&log).is_some());
+
+        println!(
+            "find {} over {} chars: {} allocations in {:?}",
+            pattern,
+            log.len(),
+            ALLOCATIONS.load(Ordering::Relaxed) - before,
+            start.elapsed()
+        );
+    }
+}
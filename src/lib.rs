@@ -1,57 +1,510 @@
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
     fmt::{self, Display, Formatter},
+    io::{self, Read},
+    ops::Range,
+    str::FromStr,
+    sync::Arc,
 };
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg(feature = "unicode")]
+use std::sync::OnceLock;
+
+use nfa::Nfa;
+
+mod nfa;
+
+#[derive(Debug, Clone)]
 pub struct Regexp {
-    tokens: Vec<Token>,
+    pattern: String,
+    /// Shared between clones, so cloning a regexp or one of its groups
+    /// doesn't copy the tokens.
+    tokens: Arc<Vec<Token>>,
+    flags: Flags,
+    /// Compiled once the whole regexp is built, see [`Regexp::compile`].
+    nfa: Option<Arc<Nfa>>,
 }
 
 impl Regexp {
     pub fn new(pattern: &str) -> Result<Self, RegexpParsingError> {
+        RegexpBuilder::new().build(pattern)
+    }
+
+    /// Like [`Regexp::new`], but reports every error instead of only the
+    /// first. After each error the offending char is escaped or removed, so
+    /// parsing can go on and find the next one, and an unbalanced group is
+    /// parsed as if its parentheses were literal. An invalid class range is
+    /// replaced by its first endpoint.
+    pub fn new_collect_errors(pattern: &str) -> Result<Self, Vec<RegexpParsingError>> {
+        let mut chars: Vec<char> = pattern.chars().collect();
+        // The index in `pattern` every char of `chars` came from.
+        let mut origins: Vec<usize> = (0..chars.len()).collect();
+        let mut errors = Vec::new();
+
+        loop {
+            let mut error = match Self::new(&chars.iter().collect::<String>()) {
+                Ok(regexp) if errors.is_empty() => return Ok(regexp),
+                Ok(_) => return Err(errors),
+                Err(error) => error,
+            };
+            let index = error.index().min(chars.len() - 1);
+
+            *error.index_mut() = origins[index];
+
+            // Every step changes the pattern, so the same error coming back
+            // means recovering from it made no progress.
+            if errors.last() == Some(&error) {
+                return Err(errors);
+            }
+
+            let range = matches!(error, RegexpParsingError::InvalidClassRange { .. });
+            errors.push(error);
+
+            let mut escape = |chars: &mut Vec<char>, i: usize| {
+                chars.insert(i, '\\');
+                origins.insert(i, origins[i]);
+            };
+
+            if range {
+                let (_, dash) = parse_class_literal(&chars, index, 0).unwrap();
+                let (_, end) = parse_class_literal(&chars, dash + 1, 0).unwrap();
+
+                chars.drain(dash..end);
+                origins.drain(dash..end);
+                continue;
+            }
+
+            match chars[index] {
+                '(' => {
+                    if let Some(end) = find_closing_paren(&chars, index) {
+                        escape(&mut chars, end);
+                    }
+
+                    escape(&mut chars, index);
+                },
+                chr if "\\.*+?()[]{}^$|".contains(chr) => escape(&mut chars, index),
+                _ => {
+                    chars.remove(index);
+                    origins.remove(index);
+                },
+            }
+        }
+    }
+
+    /// Like [`Regexp::new`], but letters match regardless of their case. Only
+    /// one-to-one case mappings are considered, so e.g. `ß` doesn't match "SS".
+    pub fn new_case_insensitive(pattern: &str) -> Result<Self, RegexpParsingError> {
+        RegexpBuilder::new().case_insensitive(true).build(pattern)
+    }
+
+    /// Builds a regexp matching exactly `text`, without interpreting any of
+    /// its chars as metacharacters.
+    pub fn literal(text: &str) -> Self {
+        Self {
+            pattern: Self::escape(text),
+            tokens: coalesce_literals(
+                text.chars()
+                    .map(|chr| (Atom::Char(chr), Quantifier::Exact))
+                    .collect(),
+            )
+            .into(),
+            flags: Flags::default(),
+            nfa: None,
+        }
+        .compiled()
+    }
+
+    /// Escapes every metacharacter in `text`, so the result can be used as a
+    /// pattern matching `text` literally.
+    pub fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+
+        for chr in text.chars() {
+            if "\\.*+?()[]{}^$|".contains(chr) {
+                escaped.push('\\');
+            }
+
+            escaped.push(chr);
+        }
+
+        escaped
+    }
+
+    /// Builds a regexp matching `self` followed by `other`. Groups of `other`
+    /// are numbered after those of `self`.
+    pub fn then(self, mut other: Regexp) -> Self {
+        let flags = self.flags;
+
+        other.shift_groups(self.group_count());
+
+        let mut tokens = Arc::unwrap_or_clone(self.tokens);
+
+        match other.flags == flags {
+            true => tokens.extend(other.tokens.iter().cloned()),
+            false => tokens.push((Atom::Expr(other), Quantifier::Exact)),
+        }
+
+        Self::from_tokens(tokens, flags)
+    }
+
+    /// Builds a regexp matching either `self` or `other`, preferring `self`.
+    /// Groups of `other` are numbered after those of `self`.
+    pub fn or(self, mut other: Regexp) -> Self {
+        let flags = self.flags;
+
+        other.shift_groups(self.group_count());
+        Self::from_tokens(
+            vec![(Atom::Alternation(vec![self, other]), Quantifier::Exact)],
+            flags,
+        )
+    }
+
+    /// Builds a regexp matching `self` any number of times, like `(?:self)*`.
+    pub fn star(self) -> Self {
+        let flags = self.flags;
+        Self::from_tokens(
+            vec![(
+                Atom::Expr(self),
+                Quantifier::Star {
+                    greed: Greed::Greedy,
+                },
+            )],
+            flags,
+        )
+    }
+
+    /// Builds a regexp matching `self` or nothing, like `(?:self)?`.
+    pub fn optional(self) -> Self {
+        let flags = self.flags;
+        let quantifier = Quantifier::Optional {
+            greed: Greed::Greedy,
+        };
+
+        Self::from_tokens(vec![(Atom::Expr(self), quantifier)], flags)
+    }
+
+    /// Builds a regexp matching `self` exactly `n` times in a row, like
+    /// `(?:self){n}`. With `n == 0` it only matches the empty string.
+    pub fn repeat(self, n: usize) -> Self {
+        let flags = self.flags;
+        let quantifier = Quantifier::Range {
+            min: n,
+            max: Some(n),
+            greed: Greed::Greedy,
+        };
+
+        Self::from_tokens(vec![(Atom::Expr(self), quantifier)], flags)
+    }
+
+    fn from_tokens(tokens: Vec<Token>, flags: Flags) -> Self {
+        let mut regexp = Self {
+            pattern: String::new(),
+            tokens: coalesce_literals(tokens).into(),
+            flags,
+            nfa: None,
+        };
+
+        regexp.pattern = regexp.to_string();
+        regexp.compiled()
+    }
+
+    /// Parses `pattern`, allowing groups to be nested `nest_limit` deep and
+    /// the tokens up to each quantifier to compile to `size_limit` bytes.
+    fn parse(
+        pattern: &str,
+        mut flags: Flags,
+        groups: &mut Vec<Option<String>>,
+        nest_limit: usize,
+        size_limit: usize,
+    ) -> Result<Self, RegexpParsingError> {
         use Atom::*;
+        use Greed::*;
         use Quantifier::*;
 
         let chars: Vec<char> = pattern.chars().collect();
         let mut tokens = Vec::new();
+        // Tokens of the current branch that precede a change of flags by a
+        // `(?i)`, enclosed in expressions with the flags they were parsed with.
+        let mut prefix = Vec::new();
+        let mut branches = Vec::new();
+        let mut branch_start = 0;
+        let mut segment_start = 0;
+        let mut size = SizeBudget::new(size_limit);
         let mut i = 0;
 
         'outer: while i < chars.len() {
             let is_last_token_exact = matches!(tokens.last(), Some((_, Exact)));
 
             match chars[i] {
-                chr if i != 0 && chars[i - 1] == '\\' => {
-                    tokens.pop();
-                    tokens.push((Char(chr), Exact));
+                '\\' => {
+                    i += 1;
+
+                    if i == chars.len() {
+                        return Err(RegexpParsingError::TrailingBackslash { index: i - 1 });
+                    }
+
+                    if chars[i] == 'Q' {
+                        let end = quote_end(&chars, i + 1);
+
+                        tokens.extend(chars[(i + 1)..end].iter().map(|&chr| (Char(chr), Exact)));
+                        i = end + 2;
+
+                        continue 'outer;
+                    }
+
+                    let value = match chars[i] {
+                        'b' => WordBoundary,
+                        'B' => NotWordBoundary,
+                        'A' => TextStart,
+                        'z' => TextEnd,
+                        chr @ '1'..='9' => {
+                            let group = chr as usize - '0' as usize;
+
+                            if group > groups.len() {
+                                return Err(RegexpParsingError::InvalidBackref { index: i - 1 });
+                            }
+
+                            Backref(group)
+                        },
+                        escape @ ('x' | 'u') => {
+                            let Some((chr, len)) = parse_code_point(escape, &chars[(i + 1)..])
+                            else {
+                                return Err(RegexpParsingError::InvalidEscape { index: i - 1 });
+                            };
+
+                            i += len;
+                            Char(chr)
+                        },
+                        chr => match CharClass::escaped(chr) {
+                            Some(class) => Class(class),
+                            None => Char(escaped_char(chr)),
+                        },
+                    };
+
+                    tokens.push((value, Exact));
+                },
+                chr if flags.ignore_whitespace && chr.is_whitespace() => {},
+                '#' if flags.ignore_whitespace => {
+                    while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                        i += 1;
+                    }
+                },
+                '(' if chars[i..].starts_with(&['(', '?', '#']) => {
+                    let Some(len) = comment_len(&chars[i..]) else {
+                        return Err(RegexpParsingError::UnterminatedComment { index: i });
+                    };
+
+                    i += len - 1;
                 },
+                ')' => return Err(RegexpParsingError::UnopenedParen { index: i }),
                 '.' => tokens.push((Wildcard, Exact)),
+                '^' => tokens.push((StartAnchor, Exact)),
+                '$' => tokens.push((EndAnchor, Exact)),
+                '[' => {
+                    let (class, j) = parse_class(&chars, i)?;
+                    tokens.push((Class(class), Exact));
+                    i = j;
+                },
+                '|' => {
+                    size.take(&tokens);
+
+                    let branch = std::mem::take(&mut prefix)
+                        .into_iter()
+                        .chain(std::mem::take(&mut tokens))
+                        .collect();
+
+                    branches.push(Self {
+                        pattern: chars[branch_start..i].iter().collect(),
+                        tokens: coalesce_literals(branch).into(),
+                        flags,
+                        nfa: None,
+                    });
+                    branch_start = i + 1;
+                    segment_start = i + 1;
+                },
                 '(' => {
-                    for j in (i..chars.len()).rev() {
-                        if chars[j] == ')' && chars[j - 1] != '\\' {
-                            let expr = Self::new(&pattern[(i + 1)..j])?;
-                            tokens.push((Expr(expr), Exact));
-                            i = j + 1;
+                    let Some(j) = find_closing_paren(&chars, i) else {
+                        return Err(RegexpParsingError::UnclosedParen { index: i });
+                    };
+
+                    let inner = &chars[(i + 1)..j];
+                    let mut inner_start = i + 1;
+                    let mut inner_flags = flags;
+                    let mut non_capturing: Option<fn(Regexp) -> Atom> = None;
+                    let mut name = None;
+
+                    match inner {
+                        ['?', ':', ..] => non_capturing = Some(Expr),
+                        ['?', '=', ..] => non_capturing = Some(Lookahead),
+                        ['?', '!', ..] => non_capturing = Some(NegLookahead),
+                        ['?', '>', ..] => non_capturing = Some(AtomicGroup),
+                        ['?', '<', ..] => {},
+                        ['?', rest @ ..] => {
+                            let (new_flags, len) = parse_flags(rest, flags).map_err(|k| {
+                                RegexpParsingError::UnknownFlag { index: i + 2 + k }
+                            })?;
+
+                            if len == rest.len() {
+                                size.take(&tokens);
+
+                                if !tokens.is_empty() {
+                                    prefix.push((
+                                        Expr(Self {
+                                            pattern: chars[segment_start..i].iter().collect(),
+                                            tokens: coalesce_literals(std::mem::take(&mut tokens))
+                                                .into(),
+                                            flags,
+                                            nfa: None,
+                                        }),
+                                        Exact,
+                                    ));
+                                }
+
+                                flags = new_flags;
+                                segment_start = j + 1;
+                                i = j + 1;
 
-                            continue 'outer;
+                                continue 'outer;
+                            }
+
+                            non_capturing = Some(Expr);
+                            inner_flags = new_flags;
+                            inner_start += len;
+                        },
+                        _ => {},
+                    }
+
+                    if non_capturing.is_some() {
+                        inner_start += 2;
+                    } else if inner.starts_with(&['?', '<']) {
+                        let Some(len) = inner.iter().position(|&chr| chr == '>') else {
+                            return Err(RegexpParsingError::UnclosedGroupName { index: i });
+                        };
+                        let group_name: String = inner[2..len].iter().collect();
+
+                        if !is_valid_group_name(&group_name) {
+                            return Err(RegexpParsingError::InvalidGroupName { index: i });
+                        }
+
+                        if groups.contains(&Some(group_name.clone())) {
+                            return Err(RegexpParsingError::DuplicateGroupName { index: i });
                         }
+
+                        inner_start += len + 1;
+                        name = Some(group_name);
+                    }
+
+                    if nest_limit == 0 {
+                        return Err(RegexpParsingError::TooDeeplyNested { index: i });
                     }
 
-                    return Err(RegexpParsingError {
-                        message: format!("unclosed parenthesis at index {}", i),
+                    let group = non_capturing.is_none().then(|| {
+                        groups.push(name.clone());
+                        groups.len()
                     });
+                    let inner: String = chars[inner_start..j].iter().collect();
+                    let size_limit = size.count(&tokens);
+                    let expr = Self::parse(&inner, inner_flags, groups, nest_limit - 1, size_limit)
+                        .map_err(|err| err.offset(inner_start))?;
+
+                    match (group, non_capturing) {
+                        (Some(group), _) => tokens.push((Group(group, name, expr), Exact)),
+                        (None, Some(wrap)) => tokens.push((wrap(expr), Exact)),
+                        (None, None) => unreachable!(),
+                    }
+
+                    i = j;
+                },
+                '*' | '+' | '?' | '{'
+                    if matches!(
+                        tokens.last(),
+                        Some((
+                            StartAnchor
+                                | EndAnchor
+                                | TextStart
+                                | TextEnd
+                                | WordBoundary
+                                | NotWordBoundary,
+                            Exact
+                        ))
+                    ) && (chars[i] != '{' || parse_range(&chars[(i + 1)..]).is_some()) =>
+                {
+                    return Err(RegexpParsingError::QuantifiedAnchor { index: i });
                 },
                 '*' if is_last_token_exact => {
                     let value = tokens.pop().unwrap().0;
-                    tokens.push((value, Star));
+                    tokens.push((value, Star { greed: Greedy }));
+                    size.spend(&tokens, i)?;
                 },
                 '+' if is_last_token_exact => {
-                    let value = tokens.last().unwrap().0.clone();
-                    tokens.push((value, Star));
+                    let value = tokens.pop().unwrap().0;
+                    tokens.push((value, Plus { greed: Greedy }));
+                    size.spend(&tokens, i)?;
+                },
+                '?' if matches!(
+                    tokens.last(),
+                    Some((
+                        _,
+                        Star { greed: Greedy }
+                            | Plus { greed: Greedy }
+                            | Optional { greed: Greedy }
+                            | Range { greed: Greedy, .. }
+                    ))
+                ) =>
+                {
+                    let (value, quantifier) = tokens.pop().unwrap();
+                    tokens.push((value, quantifier.with_greed(Lazy)));
+                },
+                '+' if matches!(
+                    tokens.last(),
+                    Some((
+                        _,
+                        Star { greed: Greedy }
+                            | Plus { greed: Greedy }
+                            | Optional { greed: Greedy }
+                    ))
+                ) =>
+                {
+                    let (value, quantifier) = tokens.pop().unwrap();
+                    tokens.push((value, quantifier.with_greed(Possessive)));
                 },
                 '?' if is_last_token_exact => {
                     let value = tokens.pop().unwrap().0;
-                    tokens.push((value, Optional));
+                    tokens.push((value, Optional { greed: Greedy }));
+                    size.spend(&tokens, i)?;
+                },
+                '{' if is_last_token_exact => {
+                    if let Some((min, max, len)) = parse_range(&chars[(i + 1)..]) {
+                        if max.is_some_and(|max| min > max) {
+                            return Err(RegexpParsingError::InvalidQuantifier { index: i });
+                        }
+
+                        let value = tokens.pop().unwrap().0;
+                        tokens.push((
+                            value,
+                            Range {
+                                min,
+                                max,
+                                greed: Greedy,
+                            },
+                        ));
+                        size.spend(&tokens, i)?;
+                        i += len + 2;
+
+                        continue 'outer;
+                    }
+
+                    tokens.push((Char('{'), Exact));
+                },
+                '*' | '+' | '?' if tokens.is_empty() => {
+                    return Err(RegexpParsingError::DanglingQuantifier { index: i });
+                },
+                '*' | '+' | '?' => {
+                    return Err(RegexpParsingError::RedundantQuantifier { index: i });
+                },
+                '{' if !tokens.is_empty() && parse_range(&chars[(i + 1)..]).is_some() => {
+                    return Err(RegexpParsingError::RedundantQuantifier { index: i });
                 },
                 chr => {
                     tokens.push((Char(chr), Exact));
@@ -61,164 +514,4863 @@ impl Regexp {
             i += 1;
         }
 
-        Ok(Self { tokens })
+        if !prefix.is_empty() {
+            prefix.extend(tokens);
+            tokens = prefix;
+        }
+
+        if !branches.is_empty() {
+            branches.push(Self {
+                pattern: chars[branch_start..].iter().collect(),
+                tokens: tokens.into(),
+                flags,
+                nfa: None,
+            });
+            tokens = vec![(Alternation(branches), Exact)];
+        }
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            tokens: coalesce_literals(tokens).into(),
+            flags,
+            nfa: None,
+        })
     }
 
+    /// Returns the pattern this regexp was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns the compiled tokens as an indented tree, with one token and its
+    /// quantifier per line and the tokens of nested expressions below it.
+    pub fn debug_tree(&self) -> String {
+        let mut tree = String::new();
+        self.write_tree(&mut tree, 0);
+        tree
+    }
+
+    fn write_tree(&self, tree: &mut String, depth: usize) {
+        for (value, quantifier) in self.tokens.iter() {
+            let label = match value {
+                Atom::Literal(chars) => format!("Literal({:?})", String::from_iter(chars)),
+                Atom::Class(class) => format!("Class({})", class),
+                Atom::Expr(_) => "Expr".to_string(),
+                Atom::Group(group, None, _) => format!("Group({})", group),
+                Atom::Group(group, Some(name), _) => format!("Group({}, {:?})", group, name),
+                Atom::Alternation(_) => "Alternation".to_string(),
+                Atom::Lookahead(_) => "Lookahead".to_string(),
+                Atom::NegLookahead(_) => "NegLookahead".to_string(),
+                Atom::AtomicGroup(_) => "AtomicGroup".to_string(),
+                value => format!("{:?}", value),
+            };
+
+            tree.push_str(&"  ".repeat(depth));
+            tree.push_str(&label);
+
+            if *quantifier != Quantifier::Exact {
+                tree.push_str(&format!(" {}", quantifier));
+            }
+
+            tree.push('\n');
+
+            for expr in value.sub_exprs() {
+                if let Atom::Alternation(_) = value {
+                    tree.push_str(&"  ".repeat(depth + 1));
+                    tree.push_str("Branch\n");
+                    expr.write_tree(tree, depth + 2);
+                } else {
+                    expr.write_tree(tree, depth + 1);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the whole of `string` is matched, as if the pattern
+    /// was surrounded by `^` and `$`. Use [`Regexp::is_match`] to look for a
+    /// match anywhere in `string`. This runs in time linear in the length of
+    /// `string`, see [`Regexp::compile`].
     pub fn matches(&self, string: &str) -> bool {
         let chars: Vec<char> = string.chars().collect();
-        self.start_match(&chars) == Match::Full
+        self.matches_chars(&chars)
     }
 
-    fn start_match(&self, chars: &[char]) -> Match {
-        let mut i = 0;
-        let mut consumed = 0;
+    /// Like [`Regexp::matches`], for callers that already have the chars of
+    /// the input and want to avoid collecting them again.
+    pub fn matches_chars(&self, chars: &[char]) -> bool {
+        if let Some(nfa) = self.compile() {
+            return nfa.matches(chars);
+        }
 
-        while i < self.tokens.len() {
-            match &self.tokens[i] {
-                (value, Quantifier::Exact) => {
-                    if consumed == chars.len() {
-                        return Match::Partial(consumed);
-                    }
+        let mut state = self.new_state(chars);
+        self.start_match(&mut state, 0, &mut |_, end| end == chars.len())
+    }
 
-                    match value_match_len_at_index(chars, consumed, value) {
-                        Match::Full => unimplemented!(),
-                        Match::Partial(just_consumed) => {
-                            if just_consumed == 0 {
-                                return Match::Partial(consumed);
-                            }
+    /// Like [`Regexp::is_match`], but reads the text from `reader` in chunks.
+    /// When [`Regexp::max_len`] bounds the matches, only about that many chars
+    /// are kept besides the current chunk. Otherwise, or if the pattern has
+    /// lookaheads, which can look past a match, the whole text is read first.
+    /// Fails if reading does or the text isn't valid UTF-8.
+    pub fn is_match_reader<R: Read>(&self, mut reader: R) -> io::Result<bool> {
+        let max_len = match self.max_len() {
+            Some(max_len) if !self.has_lookaheads() => max_len,
+            _ => {
+                let mut text = String::new();
+                reader.read_to_string(&mut text)?;
+                return Ok(self.is_match(&text));
+            },
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "stream is not valid UTF-8");
 
-                            i += 1;
-                            consumed += just_consumed;
-                        },
-                    }
+        let mut buffer = [0; 8192];
+        let mut bytes = Vec::new();
+        let mut chars = Vec::new();
+        // The index in `chars` of the next start to try. Only one char is kept
+        // before it, which is all that anchors and word boundaries look at.
+        let mut start = 0;
+
+        loop {
+            let read = reader.read(&mut buffer)?;
+            let eof = read == 0;
+
+            bytes.extend_from_slice(&buffer[..read]);
+
+            let valid = match std::str::from_utf8(&bytes) {
+                Ok(text) => text,
+                Err(error) if error.error_len().is_none() && !eof => {
+                    std::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap()
                 },
-                (value, Quantifier::Star) => {
-                    if consumed == chars.len() {
-                        if i == self.tokens.len() - 1 {
-                            return Match::Full;
-                        }
+                Err(_) => return Err(invalid()),
+            };
+            let len = valid.len();
 
-                        return Match::Partial(consumed);
-                    }
+            chars.extend(valid.chars());
+            bytes.drain(..len);
 
-                    i += 1;
+            // A match starting at `end` might still depend on chars that
+            // weren't read yet, up to the one after its last.
+            let end = match eof {
+                true => chars.len() + 1,
+                false => chars.len().saturating_sub(max_len + 1),
+            };
 
-                    let rest = Self {
-                        tokens: self.tokens[i..].to_vec(),
-                    };
-                    let mut j = i;
+            if (start..end).any(|i| self.captures_anchored(&chars, i).is_some()) {
+                return Ok(true);
+            }
 
-                    while j < chars.len() {
-                        if rest.start_match(&chars[j..]) == Match::Full {
-                            return Match::Full;
-                        }
+            if eof {
+                return Ok(false);
+            }
 
-                        match value_match_len_at_index(chars, j, value) {
-                            Match::Full => unimplemented!(),
-                            Match::Partial(just_consumed) => {
-                                if just_consumed == 0 {
-                                    break;
-                                }
+            start = start.max(end);
 
-                                consumed += just_consumed;
-                            },
-                        }
+            if start > 1 {
+                chars.drain(..(start - 1));
+                start = 1;
+            }
+        }
+    }
 
-                        j += 1;
-                    }
-                },
-                (value, Quantifier::Optional) => {
-                    if consumed == chars.len() {
-                        if i == self.tokens.len() - 1 {
-                            return Match::Full;
-                        }
+    /// Tries to match starting at the first char of `text`, like
+    /// [`Regexp::matches_at`] at offset 0, but on failure also reports how
+    /// many chars matching got through, e.g. 2 for `abc` against "abx".
+    pub fn match_prefix(&self, text: &str) -> PrefixMatch {
+        let chars: Vec<char> = text.chars().collect();
+        let mut state = self.new_state(&chars);
+        let mut end = None;
 
-                        return Match::Partial(consumed);
-                    }
+        self.start_match(&mut state, 0, &mut |_, index| {
+            end = Some(index);
+            true
+        });
 
-                    i += 1;
+        match end {
+            Some(len) => PrefixMatch::Matched(len),
+            None => PrefixMatch::Failed {
+                consumed: state.furthest,
+            },
+        }
+    }
 
-                    let rest = Self {
-                        tokens: self.tokens[i..].to_vec(),
-                    };
+    /// Returns whether the string made of only `chr` is matched, which makes
+    /// a pattern like `[a-z]` usable as a char predicate.
+    pub fn matches_char(&self, chr: char) -> bool {
+        self.matches_chars(&[chr])
+    }
 
-                    if rest.start_match(&chars[consumed..]) == Match::Full {
-                        return Match::Full;
-                    }
+    /// Returns whether `text` contains a match anywhere, unlike
+    /// [`Regexp::matches`] which requires the whole of `text` to match.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
 
-                    match value_match_len_at_index(chars, consumed, value) {
-                        Match::Full => unimplemented!(),
-                        Match::Partial(just_consumed) => consumed += just_consumed,
-                    };
-                },
-            }
+        match self.compile() {
+            Some(nfa) => nfa.is_match(&chars),
+            None => self.find_at(&chars, 0).is_some(),
         }
+    }
+
+    /// Returns the number of chars in the shortest prefix of `text` that is
+    /// matched, where e.g. `a+` stops after the first `a`.
+    pub fn shortest_match(&self, text: &str) -> Option<usize> {
+        let chars: Vec<char> = text.chars().collect();
 
-        match consumed == chars.len() {
-            true => Match::Full,
-            false => Match::Partial(consumed),
+        if let Some(nfa) = self.compile() {
+            return nfa.shortest_match(&chars);
         }
+
+        let mut state = self.new_state(&chars);
+        let mut shortest: Option<usize> = None;
+
+        self.start_match(&mut state, 0, &mut |_, end| {
+            shortest = Some(shortest.map_or(end, |shortest| shortest.min(end)));
+            false
+        });
+
+        shortest
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct RegexpParsingError {
-    pub message: String,
-}
+    /// Returns the span of the leftmost match in `string` as `(start, end)`
+    /// char offsets, so `end - start` is the number of matched chars. Empty
+    /// matches are reported as well. When the pattern compiles to an NFA, has
+    /// no literal prefix to skip offsets with and doesn't repeat anything that
+    /// can match the empty string, the span is found by the NFA in time
+    /// linear in the length of `string`, see [`Regexp::compile`].
+    pub fn find(&self, string: &str) -> Option<(usize, usize)> {
+        let chars: Vec<char> = string.chars().collect();
 
-impl Display for RegexpParsingError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.write_str(&self.message)
+        match self.compile() {
+            Some(nfa) if nfa.finds_spans() && self.literal_prefix().0.is_empty() => {
+                nfa.find(&chars)
+            },
+            _ => self.find_at(&chars, 0),
+        }
     }
-}
 
-impl Error for RegexpParsingError {}
+    /// Like [`Regexp::find`], but returns the longest match starting at the
+    /// leftmost position instead of the first one the pattern prefers, so
+    /// `a|ab` finds `ab` in "abc" rather than `a`.
+    pub fn find_longest(&self, string: &str) -> Option<(usize, usize)> {
+        let chars: Vec<char> = string.chars().collect();
 
-fn value_match_len_at_index(chars: &[char], index: usize, value: &Atom) -> Match {
-    match value {
-        Atom::Wildcard => Match::Partial(1),
-        Atom::Char(chr) => Match::Partial((chars[index] == *chr) as usize),
-        Atom::Expr(expr) => expr.start_match(chars),
+        (0..=chars.len()).find_map(|start| {
+            let mut state = self.new_state(&chars);
+            let mut longest: Option<usize> = None;
+
+            self.start_match(&mut state, start, &mut |_, end| {
+                longest = Some(longest.map_or(end, |longest| longest.max(end)));
+                false
+            });
+
+            longest.map(|end| (start, end))
+        })
     }
-}
 
-type Token = (Atom, Quantifier);
+    /// Like [`Regexp::find`], but only tries a match starting at offset 0
+    /// instead of scanning every offset.
+    pub fn find_anchored(&self, text: &str) -> Option<(usize, usize)> {
+        let chars: Vec<char> = text.chars().collect();
+        self.captures_anchored(&chars, 0)?[0]
+    }
 
-#[derive(Debug, PartialEq, Eq)]
-enum Match {
-    Full,
-    Partial(usize),
-}
+    /// Iterates over the spans of all non-overlapping matches in `string`,
+    /// using the same char offsets as [`Regexp::find`].
+    pub fn find_iter<'a>(&'a self, string: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.captures_of_all(string.chars().collect())
+            .map(|captures| captures[0].unwrap())
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum Atom {
-    Wildcard,
-    Char(char),
-    Expr(Regexp),
-}
+    /// Iterates over the spans of the matches starting at every char offset
+    /// of `text` in turn, so unlike [`Regexp::find_iter`] they may overlap.
+    pub fn find_overlapping<'a>(
+        &'a self,
+        text: &'a str,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let chars: Vec<char> = text.chars().collect();
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum Quantifier {
-    Exact,
-    Star,
-    Optional,
-}
+        (0..=chars.len()).filter_map(move |start| self.captures_anchored(&chars, start)?[0])
+    }
 
-#[test]
-fn test() {
-    let expr0 = Regexp::new("ab.?c").unwrap();
-    let expr1 = Regexp::new("a+b*\\.").unwrap();
-    let expr2 = Regexp::new("a.*b").unwrap();
+    /// Tries to match starting exactly at char offset `pos` of `text` and
+    /// returns how many chars the match spans. The chars before `pos` are
+    /// still seen by anchors and word boundaries, so `^` doesn't match there.
+    pub fn matches_at(&self, text: &str, pos: usize) -> Option<usize> {
+        let chars: Vec<char> = text.chars().collect();
 
-    assert!(expr0.matches("abc"));
-    assert!(expr0.matches("abdc"));
-    assert!(!expr0.matches("abcde"));
+        if pos > chars.len() {
+            return None;
+        }
 
-    assert!(expr1.matches("abbb."));
-    assert!(expr1.matches("aaaa."));
-    assert!(!expr1.matches("b."));
-    assert!(!expr1.matches("ab!"));
+        let (_, end) = self.captures_anchored(&chars, pos)?[0]?;
+        Some(end - pos)
+    }
 
-    assert!(expr2.matches("asadf.b"));
-    assert!(expr2.matches("ab"));
+    /// Returns whether some prefix of `text` is matched.
+    pub fn starts_with(&self, text: &str) -> bool {
+        self.matches_at(text, 0).is_some()
+    }
+
+    /// Returns whether some suffix of `text` is matched.
+    pub fn ends_with(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+
+        (0..=chars.len()).any(|start| {
+            let mut state = self.new_state(&chars);
+            self.start_match(&mut state, start, &mut |_, end| end == chars.len())
+        })
+    }
+
+    /// Returns the number of matches [`Regexp::find_iter`] finds in `text`. An
+    /// empty match is counted too, so `a*` counts 2 matches in "aa": "aa"
+    /// itself and the empty match at the end.
+    pub fn count(&self, text: &str) -> usize {
+        self.find_iter(text).count()
+    }
+
+    /// Returns the text matched by each parenthesized group of the leftmost
+    /// match, numbered by their opening parenthesis from left to right. Group
+    /// 0 is the whole match, groups that did not participate are `None`.
+    pub fn captures(&self, string: &str) -> Option<Vec<Option<String>>> {
+        let chars: Vec<char> = string.chars().collect();
+        let captures = self.captures_at(&chars, 0)?;
+
+        Some(
+            captures
+                .into_iter()
+                .map(|span| span.map(|(start, end)| chars[start..end].iter().collect()))
+                .collect(),
+        )
+    }
+
+    /// Iterates over the groups of all non-overlapping matches in `text`, as
+    /// [`Regexp::captures`] returns them for the leftmost one.
+    pub fn captures_iter<'a>(
+        &'a self,
+        text: &'a str,
+    ) -> impl Iterator<Item = Vec<Option<String>>> + 'a {
+        let chars: Vec<char> = text.chars().collect();
+
+        self.captures_of_all(chars.clone()).map(move |captures| {
+            captures
+                .into_iter()
+                .map(|span| span.map(|(start, end)| chars[start..end].iter().collect()))
+                .collect()
+        })
+    }
+
+    /// Returns the text matched by each named group of the leftmost match,
+    /// keyed by the group name.
+    pub fn captures_named(&self, string: &str) -> Option<HashMap<String, Option<String>>> {
+        let captures = self.captures(string)?;
+
+        Some(
+            self.group_names()
+                .into_iter()
+                .map(|(group, name)| (name.to_string(), captures[group].clone()))
+                .collect(),
+        )
+    }
+
+    /// Like [`Regexp::find`], but returns the span as a range of byte offsets,
+    /// so it can be used to slice `haystack` directly.
+    pub fn find_range(&self, haystack: &str) -> Option<Range<usize>> {
+        let chars: Vec<char> = haystack.chars().collect();
+        let (start, end) = self.find_at(&chars, 0)?;
+        let offsets = byte_offsets(haystack);
+
+        Some(offsets[start]..offsets[end])
+    }
+
+    /// Like [`Regexp::captures`], but returns the byte range of each group
+    /// within `haystack` instead of a copy of its text.
+    pub fn capture_ranges(&self, haystack: &str) -> Option<Vec<Option<Range<usize>>>> {
+        let chars: Vec<char> = haystack.chars().collect();
+        let captures = self.captures_at(&chars, 0)?;
+        let offsets = byte_offsets(haystack);
+
+        Some(
+            captures
+                .into_iter()
+                .map(|span| span.map(|(start, end)| offsets[start]..offsets[end]))
+                .collect(),
+        )
+    }
+
+    /// Like [`Regexp::capture_ranges`], but returns each byte range as a
+    /// `(start, end)` pair. Like [`Regexp::find_range`], these are byte
+    /// offsets that slice `text` directly, not the char offsets of
+    /// [`Regexp::find`].
+    pub fn capture_spans(&self, text: &str) -> Option<Vec<Option<(usize, usize)>>> {
+        Some(
+            self.capture_ranges(text)?
+                .into_iter()
+                .map(|range| range.map(|range| (range.start, range.end)))
+                .collect(),
+        )
+    }
+
+    /// Returns the number of groups every result of [`Regexp::captures`] has,
+    /// which is the number of capturing groups plus one for the whole match.
+    pub fn captures_len(&self) -> usize {
+        self.group_count() + 1
+    }
+
+    /// Returns whether both regexps are equal after rewriting them into a
+    /// canonical form, so `aa` equals `a{2}` and `(?:ab)+` equals `(?:ab){1,}`,
+    /// unlike with `==`, which compares them as they were parsed. Equivalent
+    /// regexps can still differ when their structure does, like `a|b` and
+    /// `[ab]`.
+    pub fn semantically_eq(&self, other: &Regexp) -> bool {
+        self.normalized() == other.normalized()
+    }
+
+    /// Returns an equivalent regexp with a simpler structure: `{1}` and `{0}`
+    /// are dropped along with what the latter repeats, adjacent identical
+    /// stars like `a*a*` are merged and non-capturing groups around a single
+    /// atom are removed. Capturing groups are always kept.
+    pub fn simplify(&self) -> Regexp {
+        let mut tokens: Vec<Token> = Vec::new();
+
+        for (value, quantifier) in self.tokens.iter() {
+            let mut value = value.clone();
+
+            for expr in value.sub_exprs_mut() {
+                *expr = expr.simplify();
+            }
+
+            let has_groups = matches!(value, Atom::Group(..))
+                || value.sub_exprs().iter().any(|expr| expr.group_count() > 0);
+            let mut token = match *quantifier {
+                Quantifier::Range { max: Some(0), .. } if !has_groups => continue,
+                Quantifier::Range {
+                    min: 1,
+                    max: Some(1),
+                    ..
+                } => (value, Quantifier::Exact),
+                ref quantifier => (value, quantifier.clone()),
+            };
+
+            if let (Atom::Expr(expr), quantifier) = &token {
+                if let [(inner, inner_quantifier)] = &expr.tokens[..] {
+                    let repeatable = matches!(
+                        inner,
+                        Atom::Char(_)
+                            | Atom::Class(_)
+                            | Atom::Wildcard
+                            | Atom::Group(..)
+                            | Atom::AtomicGroup(_)
+                            | Atom::Backref(_)
+                    );
+
+                    if expr.flags == self.flags && !matches!(inner, Atom::Alternation(_)) {
+                        if *quantifier == Quantifier::Exact {
+                            token = (inner.clone(), inner_quantifier.clone());
+                        } else if *inner_quantifier == Quantifier::Exact && repeatable {
+                            token = (inner.clone(), quantifier.clone());
+                        }
+                    }
+                }
+            }
+
+            if matches!(token.1, Quantifier::Star { .. }) && tokens.last() == Some(&token) {
+                continue;
+            }
+
+            tokens.push(token);
+        }
+
+        Self::from_tokens(tokens, self.flags)
+    }
+
+    /// Returns the fewest chars a match can span.
+    pub fn min_len(&self) -> usize {
+        self.len_bounds().0
+    }
+
+    /// Returns the most chars a match can span, or `None` if a quantifier
+    /// like `*` or `+` makes it unbounded. A backreference is assumed to
+    /// match anything, which makes it unbounded too.
+    pub fn max_len(&self) -> Option<usize> {
+        self.len_bounds().1
+    }
+
+    pub fn name_to_index(&self, name: &str) -> Option<usize> {
+        self.group_names()
+            .into_iter()
+            .find_map(|(group, group_name)| (group_name == name).then_some(group))
+    }
+
+    /// Returns `haystack` with its leftmost match replaced by `replacement`,
+    /// or an unchanged copy if there is no match. The replacement can refer
+    /// to groups of the match, see [`Regexp::replace_all`].
+    pub fn replace(&self, haystack: &str, replacement: &str) -> String {
+        self.replacen(haystack, 1, replacement)
+    }
+
+    /// Returns `haystack` with every non-overlapping match replaced by
+    /// `replacement`. In the replacement, `$1` or `${1}` stands for the text
+    /// of group 1 and `${name}` for that of a named group, with `$0` being
+    /// the whole match. Groups that didn't participate or don't exist are
+    /// replaced by nothing, and `$$` is a literal `$`.
+    pub fn replace_all(&self, haystack: &str, replacement: &str) -> String {
+        self.replacen(haystack, 0, replacement)
+    }
+
+    /// Returns `haystack` with every non-overlapping match replaced by the
+    /// result of calling `f` with the matched text.
+    pub fn replace_with<F: FnMut(&str) -> String>(&self, haystack: &str, mut f: F) -> String {
+        self.replace_matches(haystack, usize::MAX, |chars, captures| {
+            let (start, end) = captures[0].unwrap();
+            f(&String::from_iter(&chars[start..end]))
+        })
+    }
+
+    /// Returns `haystack` with its first `count` non-overlapping matches
+    /// replaced by `replacement`, like [`Regexp::replace_all`] does with all
+    /// of them. A `count` of 0 replaces every match as well.
+    pub fn replacen(&self, haystack: &str, count: usize, replacement: &str) -> String {
+        let limit = if count == 0 { usize::MAX } else { count };
+        self.replace_matches(haystack, limit, |chars, captures| {
+            self.expand(replacement, chars, captures)
+        })
+    }
+
+    fn replace_matches<F>(&self, haystack: &str, limit: usize, mut f: F) -> String
+    where
+        F: FnMut(&[char], &[Option<(usize, usize)>]) -> String,
+    {
+        let chars: Vec<char> = haystack.chars().collect();
+        let mut result = String::new();
+        let mut last = 0;
+
+        for captures in self.captures_of_all(chars.clone()).take(limit) {
+            let (start, end) = captures[0].unwrap();
+
+            result.extend(&chars[last..start]);
+            result.push_str(&f(&chars, &captures));
+            last = end;
+        }
+
+        result.extend(&chars[last..]);
+        result
+    }
+
+    /// Substitutes the group references in a replacement `template`, as
+    /// described in [`Regexp::replace_all`].
+    fn expand(
+        &self,
+        template: &str,
+        chars: &[char],
+        captures: &[Option<(usize, usize)>],
+    ) -> String {
+        let template: Vec<char> = template.chars().collect();
+        let mut result = String::new();
+        let mut i = 0;
+
+        while i < template.len() {
+            let rest = &template[(i + 1)..];
+            let digits = rest.iter().take_while(|chr| chr.is_ascii_digit()).count();
+            let reference = match (template[i], rest) {
+                ('$', ['$', ..]) => {
+                    result.push('$');
+                    i += 2;
+                    continue;
+                },
+                ('$', ['{', ..]) => rest
+                    .iter()
+                    .position(|&chr| chr == '}')
+                    .map(|end| (String::from_iter(&rest[1..end]), end + 1)),
+                ('$', _) if digits > 0 => Some((String::from_iter(&rest[..digits]), digits)),
+                _ => None,
+            };
+
+            let Some((reference, len)) = reference else {
+                result.push(template[i]);
+                i += 1;
+                continue;
+            };
+            let group = reference
+                .parse()
+                .ok()
+                .or_else(|| self.name_to_index(&reference));
+
+            if let Some(&Some((start, end))) = group.and_then(|group| captures.get(group)) {
+                result.extend(&chars[start..end]);
+            }
+
+            i += len + 1;
+        }
+
+        result
+    }
+
+    /// Splits `text` on every non-overlapping match, returning the pieces in
+    /// between. Matches at the edges or next to each other produce empty
+    /// pieces, and zero-width matches split between chars, so `x*` splits
+    /// "ab" into `["", "a", "b", ""]`.
+    pub fn split<'a>(&'a self, text: &'a str) -> Vec<String> {
+        self.split_iter(text).map(String::from).collect()
+    }
+
+    /// Like [`Regexp::split`], but lazily yields the pieces as slices of
+    /// `text` instead of collecting them.
+    pub fn split_iter<'a>(&'a self, text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let offsets = byte_offsets(text);
+        let mut matches = self.find_iter(text);
+        // The byte index where the next piece starts, until the last one.
+        let mut last = Some(0);
+
+        std::iter::from_fn(move || {
+            let start = last?;
+
+            match matches.next() {
+                Some((match_start, match_end)) => {
+                    last = Some(offsets[match_end]);
+                    Some(&text[start..offsets[match_start]])
+                },
+                None => {
+                    last = None;
+                    Some(&text[start..])
+                },
+            }
+        })
+    }
+
+    /// Like [`Regexp::split`], but without the empty last piece left by a
+    /// match at the end of `text`, so `,` splits "a,b," into `["a", "b"]`.
+    pub fn split_terminator<'a>(&'a self, text: &'a str) -> Vec<String> {
+        let mut pieces = self.split(text);
+
+        if pieces.len() > 1 && pieces.last().is_some_and(String::is_empty) {
+            pieces.pop();
+        }
+
+        pieces
+    }
+
+    /// Like [`Regexp::matches`], but over bytes that need not be valid UTF-8.
+    /// Every byte is matched as the char with the same value, so ASCII
+    /// patterns behave as usual, while a char like `é` in the pattern matches
+    /// the single byte `0xE9` rather than its UTF-8 encoding.
+    pub fn matches_bytes(&self, bytes: &[u8]) -> bool {
+        self.matches_chars(&latin1_chars(bytes))
+    }
+
+    /// Like [`Regexp::find`], but over bytes as described in
+    /// [`Regexp::matches_bytes`]. The span is given in byte offsets.
+    pub fn find_bytes(&self, bytes: &[u8]) -> Option<(usize, usize)> {
+        self.find_at(&latin1_chars(bytes), 0)
+    }
+
+    /// Iterates over the group spans of all non-overlapping matches in
+    /// `chars`, moving on by a char after an empty match.
+    fn captures_of_all(
+        &self,
+        chars: Vec<char>,
+    ) -> impl Iterator<Item = Vec<Option<(usize, usize)>>> + '_ {
+        let mut start = 0;
+
+        std::iter::from_fn(move || {
+            if start > chars.len() {
+                return None;
+            }
+
+            let captures = self.captures_at(&chars, start)?;
+            let (i, j) = captures[0].unwrap();
+            start = if i == j { j + 1 } else { j };
+
+            Some(captures)
+        })
+    }
+
+    fn find_at(&self, chars: &[char], start: usize) -> Option<(usize, usize)> {
+        self.captures_at(chars, start)?[0]
+    }
+
+    fn captures_at(&self, chars: &[char], start: usize) -> Option<Vec<Option<(usize, usize)>>> {
+        let (prefix, _) = self.literal_prefix();
+
+        (start..=chars.len())
+            .filter(|&i| chars[i..].starts_with(&prefix))
+            .find_map(|i| self.captures_anchored(chars, i))
+    }
+
+    /// Returns the chars every match starts with, so offsets where they don't
+    /// follow can be skipped without trying to match there, and whether they
+    /// are all that is matched.
+    fn literal_prefix(&self) -> (Vec<char>, bool) {
+        let mut prefix = Vec::new();
+
+        if self.flags.case_insensitive {
+            return (prefix, false);
+        }
+
+        for (value, quantifier) in self.tokens.iter() {
+            match (value, quantifier) {
+                (Atom::Char(chr), Quantifier::Exact) => prefix.push(*chr),
+                (Atom::Literal(chars), Quantifier::Exact) => prefix.extend(chars),
+                (Atom::Char(chr), Quantifier::Plus { .. }) => {
+                    prefix.push(*chr);
+                    return (prefix, false);
+                },
+                (
+                    Atom::Expr(expr) | Atom::Group(_, _, expr) | Atom::AtomicGroup(expr),
+                    Quantifier::Exact,
+                ) => {
+                    let (inner, complete) = expr.literal_prefix();
+                    prefix.extend(inner);
+
+                    if !complete {
+                        return (prefix, false);
+                    }
+                },
+                (value, Quantifier::Exact) if value.len_bounds() == (0, Some(0)) => {},
+                _ => return (prefix, false),
+            }
+        }
+
+        (prefix, true)
+    }
+
+    fn captures_anchored(
+        &self,
+        chars: &[char],
+        start: usize,
+    ) -> Option<Vec<Option<(usize, usize)>>> {
+        let mut state = self.new_state(chars);
+        let found = self.start_match(&mut state, start, &mut |state, end| {
+            state.captures[0] = Some((start, end));
+            true
+        });
+
+        found.then_some(state.captures)
+    }
+
+    fn new_state<'a>(&self, chars: &'a [char]) -> State<'a> {
+        let mut state = State::new(chars, self.group_count());
+
+        // Backreferences make the outcome depend on what was captured before,
+        // which the memo doesn't account for.
+        state.memoize = !self.has_backrefs();
+        state
+    }
+
+    /// Writes out repetitions with a fixed count, replaces ranges that mean
+    /// `*`, `+` or `?` with those and inlines non-capturing groups that
+    /// don't change the flags.
+    fn normalized(&self) -> Self {
+        let mut tokens = Vec::new();
+        let mut push = |value: Atom, quantifier| match value {
+            Atom::Expr(expr) if quantifier == Quantifier::Exact && expr.flags == self.flags => {
+                tokens.extend(expr.tokens.iter().cloned())
+            },
+            value => tokens.push((value, quantifier)),
+        };
+
+        for (value, quantifier) in self.tokens.iter() {
+            let mut value = value.clone();
+
+            for expr in value.sub_exprs_mut() {
+                *expr = expr.normalized();
+            }
+
+            let quantifier = match *quantifier {
+                Quantifier::Range {
+                    min: 0,
+                    max: None,
+                    greed,
+                } => Quantifier::Star { greed },
+                Quantifier::Range {
+                    min: 1,
+                    max: None,
+                    greed,
+                } => Quantifier::Plus { greed },
+                Quantifier::Range {
+                    min: 0,
+                    max: Some(1),
+                    greed,
+                } => Quantifier::Optional { greed },
+                Quantifier::Range {
+                    min,
+                    max: Some(max),
+                    ..
+                } if min == max => {
+                    for _ in 0..min {
+                        push(value.clone(), Quantifier::Exact);
+                    }
+
+                    continue;
+                },
+                ref quantifier => quantifier.clone(),
+            };
+
+            push(value, quantifier);
+        }
+
+        Self {
+            pattern: self.pattern.clone(),
+            tokens: coalesce_literals(tokens).into(),
+            flags: self.flags,
+            nfa: None,
+        }
+    }
+
+    fn len_bounds(&self) -> (usize, Option<usize>) {
+        self.tokens
+            .iter()
+            .fold((0, Some(0)), |(min, max), (value, quantifier)| {
+                let (value_min, value_max) = value.len_bounds();
+                let (times_min, times_max) = quantifier.bounds();
+                let value_max = match (value_max, times_max) {
+                    (Some(0), _) | (_, Some(0)) => Some(0),
+                    (Some(value_max), Some(times_max)) => value_max.checked_mul(times_max),
+                    _ => None,
+                };
+
+                (
+                    min.saturating_add(value_min.saturating_mul(times_min)),
+                    max.zip(value_max)
+                        .and_then(|(max, value_max)| max.checked_add(value_max)),
+                )
+            })
+    }
+
+    fn has_lookaheads(&self) -> bool {
+        self.tokens.iter().any(|(value, _)| {
+            matches!(value, Atom::Lookahead(_) | Atom::NegLookahead(_))
+                || value.sub_exprs().iter().any(Self::has_lookaheads)
+        })
+    }
+
+    fn has_backrefs(&self) -> bool {
+        self.tokens.iter().any(|(value, _)| {
+            matches!(value, Atom::Backref(_)) || value.sub_exprs().iter().any(Self::has_backrefs)
+        })
+    }
+
+    fn group_count(&self) -> usize {
+        self.tokens
+            .iter()
+            .map(|(value, _)| {
+                let group = match value {
+                    Atom::Group(group, ..) => *group,
+                    _ => 0,
+                };
+
+                value
+                    .sub_exprs()
+                    .iter()
+                    .map(Self::group_count)
+                    .fold(group, usize::max)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn shift_groups(&mut self, by: usize) {
+        self.nfa = None;
+
+        for (value, _) in Arc::make_mut(&mut self.tokens) {
+            if let Atom::Group(group, ..) = value {
+                *group += by;
+            }
+
+            for expr in value.sub_exprs_mut() {
+                expr.shift_groups(by);
+            }
+        }
+    }
+
+    fn group_names(&self) -> Vec<(usize, &str)> {
+        let mut names = Vec::new();
+
+        for (value, _) in self.tokens.iter() {
+            if let Atom::Group(group, Some(name), _) = value {
+                names.push((*group, name.as_str()));
+            }
+
+            names.extend(value.sub_exprs().iter().flat_map(Self::group_names));
+        }
+
+        names
+    }
+
+    /// Returns the NFA compiled from the tokens, which answers whether and
+    /// where a string is matched without the exponential backtracking of
+    /// [`Regexp::start_match`]. It is `None` if the pattern uses features that
+    /// depend on backtracking, like backreferences, which are then matched by
+    /// backtracking alone.
+    fn compile(&self) -> Option<&Nfa> {
+        self.nfa.as_deref()
+    }
+
+    /// Sets the NFA returned by [`Regexp::compile`]. Patterns only allowed by
+    /// a raised [`RegexpBuilder::size_limit`] are left to backtracking, so
+    /// building them doesn't allocate their whole NFA.
+    fn compiled(mut self) -> Self {
+        if Nfa::size_of(&self) <= DEFAULT_SIZE_LIMIT {
+            self.nfa = Nfa::new(&self).map(Arc::new);
+        }
+
+        self
+    }
+
+    fn start_match<'a>(&'a self, state: &mut State<'a>, index: usize, next: Next) -> bool {
+        run(state, Goal::Tokens(&self.tokens, self.flags), index, next)
+    }
+}
+
+/// Regexps are equal when they compile to the same tokens with the same
+/// flags, however their patterns were spelled. This is structural, so `aa`
+/// and `a{2}` are not equal, see [`Regexp::semantically_eq`].
+impl PartialEq for Regexp {
+    fn eq(&self, other: &Self) -> bool {
+        self.tokens == other.tokens && self.flags == other.flags
+    }
+}
+
+impl Eq for Regexp {}
+
+/// Writes a pattern reconstructed from the compiled tokens. It may be spelled
+/// differently than [`Regexp::as_str`], but parses back into an equal regexp.
+/// Flags are written inline, like `(?i)`.
+impl Display for Regexp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Pattern(self, Flags::default()))
+    }
+}
+
+/// Displays a regexp as it would be written where the flags `.1` are in
+/// effect, switching to its own flags first if they differ.
+struct Pattern<'r>(&'r Regexp, Flags);
+
+impl Display for Pattern<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Pattern(regexp, outer) = *self;
+        let special = |chr: char| {
+            "\\.*+?()[]{}^$|".contains(chr)
+                || regexp.flags.ignore_whitespace && (chr.is_whitespace() || chr == '#')
+        };
+
+        if let [(Atom::Alternation(branches), Quantifier::Exact)] = &regexp.tokens[..] {
+            return write_branches(f, branches, outer);
+        }
+
+        if regexp.flags != outer {
+            write!(f, "(?{})", flag_changes(outer, regexp.flags))?;
+        }
+
+        let flags = regexp.flags;
+
+        for (value, quantifier) in regexp.tokens.iter() {
+            match value {
+                Atom::StartAnchor => f.write_str("^")?,
+                Atom::EndAnchor => f.write_str("$")?,
+                Atom::WordBoundary => f.write_str("\\b")?,
+                Atom::NotWordBoundary => f.write_str("\\B")?,
+                Atom::TextStart => f.write_str("\\A")?,
+                Atom::TextEnd => f.write_str("\\z")?,
+                Atom::Wildcard => f.write_str(".")?,
+                Atom::Char(chr) => write_escaped(f, *chr, special)?,
+                Atom::Literal(chars) => {
+                    for &chr in chars {
+                        write_escaped(f, chr, special)?;
+                    }
+                },
+                Atom::Class(class) => write!(f, "{}", class)?,
+                Atom::Expr(expr) => {
+                    let changes = flag_changes(flags, expr.flags);
+                    write!(f, "(?{}:{})", changes, Pattern(expr, expr.flags))?
+                },
+                Atom::Group(_, None, expr) => write!(f, "({})", Pattern(expr, flags))?,
+                Atom::Group(_, Some(name), expr) => {
+                    write!(f, "(?<{}>{})", name, Pattern(expr, flags))?
+                },
+                Atom::Alternation(branches) => {
+                    f.write_str("(?:")?;
+                    write_branches(f, branches, flags)?;
+                    f.write_str(")")?
+                },
+                Atom::Lookahead(expr) => write!(f, "(?={})", Pattern(expr, flags))?,
+                Atom::NegLookahead(expr) => write!(f, "(?!{})", Pattern(expr, flags))?,
+                Atom::AtomicGroup(expr) => write!(f, "(?>{})", Pattern(expr, flags))?,
+                Atom::Backref(group) => write!(f, "\\{}", group)?,
+            }
+
+            write!(f, "{}", quantifier)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes `branches` separated by `|`. Inline flags carry over into the
+/// following branches, so each branch only switches from the flags of the
+/// one before.
+fn write_branches(f: &mut Formatter<'_>, branches: &[Regexp], mut flags: Flags) -> fmt::Result {
+    for (i, branch) in branches.iter().enumerate() {
+        if i > 0 {
+            f.write_str("|")?;
+        }
+
+        write!(f, "{}", Pattern(branch, flags))?;
+        flags = branch.flags;
+    }
+
+    Ok(())
+}
+
+impl FromStr for Regexp {
+    type Err = RegexpParsingError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Self::new(pattern)
+    }
+}
+
+/// Serializes a [`Regexp`] as its source pattern. Flags set through a
+/// [`RegexpBuilder`] are not part of the pattern and are not preserved.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Regexp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Regexp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+impl TryFrom<&str> for Regexp {
+    type Error = RegexpParsingError;
+
+    fn try_from(pattern: &str) -> Result<Self, Self::Error> {
+        Self::new(pattern)
+    }
+}
+
+impl TryFrom<String> for Regexp {
+    type Error = RegexpParsingError;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Self::new(&pattern)
+    }
+}
+
+/// The outcome of [`Regexp::match_prefix`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefixMatch {
+    /// A match spans this many chars from the start.
+    Matched(usize),
+    /// There is no match, but the first `consumed` chars were matched by
+    /// some way of trying before it failed.
+    Failed { consumed: usize },
+}
+
+/// Counts how many bytes the tokens of a pattern compile to while it is
+/// parsed, see [`RegexpBuilder::size_limit`].
+struct SizeBudget {
+    limit: usize,
+    used: usize,
+    /// How many of the current tokens are counted in `used`.
+    counted: usize,
+}
+
+impl SizeBudget {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: 0,
+            counted: 0,
+        }
+    }
+
+    /// Counts the tokens not counted yet and returns how many bytes are left.
+    fn count(&mut self, tokens: &[Token]) -> usize {
+        self.used = self
+            .used
+            .saturating_add(nfa::tokens_size(&tokens[self.counted..]));
+        self.counted = tokens.len();
+        self.limit.saturating_sub(self.used)
+    }
+
+    /// Like [`SizeBudget::count`], but fails at the quantifier at `index`
+    /// if the tokens take more than the limit.
+    fn spend(&mut self, tokens: &[Token], index: usize) -> Result<(), RegexpParsingError> {
+        self.count(tokens);
+
+        match self.used > self.limit {
+            true => Err(RegexpParsingError::TooLarge { index }),
+            false => Ok(()),
+        }
+    }
+
+    /// Counts the tokens before they are moved out of the current ones.
+    fn take(&mut self, tokens: &[Token]) {
+        self.count(tokens);
+        self.counted = 0;
+    }
+}
+
+/// How deep groups can be nested by default, see [`RegexpBuilder::nest_limit`].
+const DEFAULT_NEST_LIMIT: usize = 100;
+
+/// How many bytes a compiled regexp can take by default, see
+/// [`RegexpBuilder::size_limit`].
+const DEFAULT_SIZE_LIMIT: usize = 10 << 20;
+
+/// Configures the flags a [`Regexp`] is compiled with.
+#[derive(Debug, Clone)]
+pub struct RegexpBuilder {
+    flags: Flags,
+    nest_limit: usize,
+    size_limit: usize,
+}
+
+impl Default for RegexpBuilder {
+    fn default() -> Self {
+        Self {
+            flags: Flags::default(),
+            nest_limit: DEFAULT_NEST_LIMIT,
+            size_limit: DEFAULT_SIZE_LIMIT,
+        }
+    }
+}
+
+impl RegexpBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how deep groups can be nested, 100 by default. Deeper patterns
+    /// fail with [`RegexpParsingError::TooDeeplyNested`] instead of
+    /// overflowing the stack while parsing. Matching doesn't recurse, so
+    /// neither the nesting nor the length of the input grows its stack.
+    pub fn nest_limit(&mut self, limit: usize) -> &mut Self {
+        self.nest_limit = limit;
+        self
+    }
+
+    /// Sets how many bytes the compiled form of a pattern can take, 10 MiB by
+    /// default. Counted repetitions are compiled into that many copies, so
+    /// this rejects patterns like `a{1000000}` with
+    /// [`RegexpParsingError::TooLarge`] at the quantifier that exceeds it.
+    pub fn size_limit(&mut self, bytes: usize) -> &mut Self {
+        self.size_limit = bytes;
+        self
+    }
+
+    /// Makes letters match regardless of their case, see
+    /// [`Regexp::new_case_insensitive`].
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.flags.case_insensitive = yes;
+        self
+    }
+
+    /// Makes `.` match `\n` as well, which it doesn't by default.
+    pub fn dot_matches_newline(&mut self, yes: bool) -> &mut Self {
+        self.flags.dot_matches_newline = yes;
+        self
+    }
+
+    /// Makes `^` and `$` match at the start and end of every line instead of
+    /// only at the start and end of the string.
+    pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// Ignores unescaped whitespace in the pattern and treats `#` as the start
+    /// of a comment running to the end of the line. Literal whitespace can
+    /// still be matched with `\ ` or inside a class like `[ ]`.
+    pub fn ignore_whitespace(&mut self, yes: bool) -> &mut Self {
+        self.flags.ignore_whitespace = yes;
+        self
+    }
+
+    pub fn build(&self, pattern: &str) -> Result<Regexp, RegexpParsingError> {
+        let regexp = Regexp::parse(
+            pattern,
+            self.flags,
+            &mut Vec::new(),
+            self.nest_limit,
+            self.size_limit,
+        )?;
+
+        // Without a quantifier past the limit, like for a long literal, no
+        // part of the pattern is to blame more than another.
+        if Nfa::size_of(&regexp) > self.size_limit {
+            return Err(RegexpParsingError::TooLarge { index: 0 });
+        }
+
+        Ok(regexp.compiled())
+    }
+}
+
+/// An error produced while parsing a pattern. Every variant carries the char
+/// offset into the pattern of the construct that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegexpParsingError {
+    UnclosedParen { index: usize },
+    UnterminatedClass { index: usize },
+    EmptyClass { index: usize },
+    InvalidClassRange { index: usize },
+    InvalidQuantifier { index: usize },
+    TrailingBackslash { index: usize },
+    UnclosedGroupName { index: usize },
+    InvalidGroupName { index: usize },
+    DuplicateGroupName { index: usize },
+    DanglingQuantifier { index: usize },
+    RedundantQuantifier { index: usize },
+    InvalidEscape { index: usize },
+    UnknownPosixClass { index: usize },
+    InvalidBackref { index: usize },
+    UnknownFlag { index: usize },
+    UnterminatedComment { index: usize },
+    UnopenedParen { index: usize },
+    TooDeeplyNested { index: usize },
+    TooLarge { index: usize },
+    QuantifiedAnchor { index: usize },
+}
+
+impl RegexpParsingError {
+    pub fn index(&self) -> usize {
+        match *self {
+            Self::UnclosedParen { index }
+            | Self::UnterminatedClass { index }
+            | Self::EmptyClass { index }
+            | Self::InvalidClassRange { index }
+            | Self::InvalidQuantifier { index }
+            | Self::TrailingBackslash { index }
+            | Self::UnclosedGroupName { index }
+            | Self::InvalidGroupName { index }
+            | Self::DuplicateGroupName { index }
+            | Self::DanglingQuantifier { index }
+            | Self::RedundantQuantifier { index }
+            | Self::InvalidEscape { index }
+            | Self::UnknownPosixClass { index }
+            | Self::InvalidBackref { index }
+            | Self::UnknownFlag { index }
+            | Self::UnterminatedComment { index }
+            | Self::UnopenedParen { index }
+            | Self::TooDeeplyNested { index }
+            | Self::TooLarge { index }
+            | Self::QuantifiedAnchor { index } => index,
+        }
+    }
+
+    fn index_mut(&mut self) -> &mut usize {
+        match self {
+            Self::UnclosedParen { index }
+            | Self::UnterminatedClass { index }
+            | Self::EmptyClass { index }
+            | Self::InvalidClassRange { index }
+            | Self::InvalidQuantifier { index }
+            | Self::TrailingBackslash { index }
+            | Self::UnclosedGroupName { index }
+            | Self::InvalidGroupName { index }
+            | Self::DuplicateGroupName { index }
+            | Self::DanglingQuantifier { index }
+            | Self::RedundantQuantifier { index }
+            | Self::InvalidEscape { index }
+            | Self::UnknownPosixClass { index }
+            | Self::InvalidBackref { index }
+            | Self::UnknownFlag { index }
+            | Self::UnterminatedComment { index }
+            | Self::UnopenedParen { index }
+            | Self::TooDeeplyNested { index }
+            | Self::TooLarge { index }
+            | Self::QuantifiedAnchor { index } => index,
+        }
+    }
+
+    fn offset(mut self, by: usize) -> Self {
+        *self.index_mut() += by;
+        self
+    }
+}
+
+impl Display for RegexpParsingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Self::UnclosedParen { .. } => "unclosed parenthesis",
+            Self::UnterminatedClass { .. } => "unterminated character class",
+            Self::EmptyClass { .. } => "empty character class",
+            Self::InvalidClassRange { .. } => "invalid character class range",
+            Self::InvalidQuantifier { .. } => "invalid quantifier",
+            Self::TrailingBackslash { .. } => "trailing backslash",
+            Self::UnclosedGroupName { .. } => "unclosed group name",
+            Self::InvalidGroupName { .. } => "invalid group name",
+            Self::DuplicateGroupName { .. } => "duplicate group name",
+            Self::DanglingQuantifier { .. } => "quantifier without anything to repeat",
+            Self::RedundantQuantifier { .. } => "redundant quantifier",
+            Self::InvalidEscape { .. } => "invalid escape sequence",
+            Self::UnknownPosixClass { .. } => "unknown POSIX character class",
+            Self::InvalidBackref { .. } => "backreference to an undefined group",
+            Self::UnknownFlag { .. } => "unknown flag",
+            Self::UnterminatedComment { .. } => "unterminated comment",
+            Self::UnopenedParen { .. } => "unmatched closing parenthesis",
+            Self::TooDeeplyNested { .. } => "pattern too deeply nested",
+            Self::TooLarge { .. } => "compiled pattern exceeds the size limit",
+            Self::QuantifiedAnchor { .. } => "quantifier applied to an anchor",
+        };
+
+        write!(f, "{} at index {}", message, self.index())
+    }
+}
+
+impl Error for RegexpParsingError {}
+
+struct State<'a> {
+    chars: &'a [char],
+    captures: Vec<Option<(usize, usize)>>,
+    /// Repeats already known not to match at an index, see [`Goal::Repeat`].
+    failed: HashSet<(usize, Repeat)>,
+    repeats: usize,
+    memoize: bool,
+    /// The furthest index any atom was tried at, see [`Regexp::match_prefix`].
+    furthest: usize,
+    /// The ways left to match if the current one fails, latest last.
+    choices: Vec<Backtrack<'a>>,
+    /// What is left to match after each goal, linked through their parents.
+    frames: Vec<(Frame<'a>, usize)>,
+    /// The captures set while matching, with the values they replaced.
+    trail: Vec<(usize, Option<(usize, usize)>)>,
+}
+
+impl<'a> State<'a> {
+    fn new(chars: &'a [char], group_count: usize) -> Self {
+        Self {
+            chars,
+            captures: vec![None; group_count + 1],
+            failed: HashSet::new(),
+            repeats: 0,
+            memoize: true,
+            furthest: 0,
+            choices: Vec::new(),
+            frames: Vec::new(),
+            trail: Vec::new(),
+        }
+    }
+
+    fn push_frame(&mut self, frame: Frame<'a>, parent: usize) -> usize {
+        self.frames.push((frame, parent));
+        self.frames.len() - 1
+    }
+
+    fn push_choice(&mut self, choice: Choice<'a>) {
+        self.choices.push(Backtrack {
+            choice,
+            frames: self.frames.len(),
+            trail: self.trail.len(),
+        });
+    }
+
+    fn set_capture(&mut self, group: usize, span: (usize, usize)) {
+        let previous = self.captures[group].replace(span);
+        self.trail.push((group, previous));
+    }
+
+    /// Restores the captures that were set after the first `len` in the trail.
+    fn undo_captures(&mut self, len: usize) {
+        for (group, previous) in self.trail.drain(len..).rev() {
+            self.captures[group] = previous;
+        }
+    }
+}
+
+type Next<'n> = &'n mut dyn FnMut(&mut State, usize) -> bool;
+
+/// The parent of the outermost frame, where matching continues with `next`.
+const ROOT: usize = usize::MAX;
+
+/// Where matching is: about to match `goal` at `index`, then continue with
+/// the innermost of the [`State::frames`], `frame`.
+#[derive(Clone, Copy)]
+struct Thread<'a> {
+    goal: Goal<'a>,
+    index: usize,
+    frame: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Goal<'a> {
+    Tokens(&'a [Token], Flags),
+    Atom(&'a Atom, Flags),
+    /// Matches the atom as many times as the repeat allows. With the same
+    /// continuation, the outcome only depends on the index and the
+    /// repetitions left, so failures are remembered to avoid retrying them
+    /// through every way of splitting the input between nested repeats.
+    Repeat(&'a Atom, Flags, Repeat),
+    /// Continues with the innermost frame.
+    Return,
+}
+
+#[derive(Clone, Copy)]
+enum Frame<'a> {
+    Tokens(&'a [Token], Flags),
+    /// One more repetition of the atom was matched from `start`, which may
+    /// only be empty if it was `required` by the minimum.
+    Repeat {
+        value: &'a Atom,
+        flags: Flags,
+        remaining: Repeat,
+        start: usize,
+        required: bool,
+    },
+    CloseGroup(usize, usize),
+    /// Ends an atomic group, or a lookahead at the index it started at, by
+    /// dropping the choices it left.
+    Cut(usize, Option<usize>),
+    /// Ends a negative lookahead whose expression matched, which fails it.
+    NegLookahead(usize),
+}
+
+struct Backtrack<'a> {
+    choice: Choice<'a>,
+    /// The number of frames and captures set when the choice was made.
+    frames: usize,
+    trail: usize,
+}
+
+enum Choice<'a> {
+    Resume(Thread<'a>),
+    /// Ends a lookahead or atomic group whose expression didn't match.
+    Fail,
+    /// Records that the repeat didn't match at the index.
+    Failed(usize, Repeat),
+}
+
+/// The repetitions left of a quantified atom. Every time matching reaches a
+/// quantifier it gets a new `id`, because the continuation can differ.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Repeat {
+    id: usize,
+    min: usize,
+    max: Option<usize>,
+    lazy: bool,
+}
+
+/// Matches `goal` at `index`, then calls `next` with where it ended until it
+/// agrees. Instead of recursing, the ways left to try are kept in `state`, so
+/// the length of the input doesn't grow the stack.
+fn run<'a>(state: &mut State<'a>, goal: Goal<'a>, index: usize, next: Next) -> bool {
+    let mut at = Thread {
+        goal,
+        index,
+        frame: ROOT,
+    };
+
+    let found = loop {
+        let matched = match at.goal {
+            Goal::Return if at.frame == ROOT => {
+                if next(state, at.index) {
+                    break true;
+                }
+
+                false
+            },
+            _ => step(state, &mut at),
+        };
+
+        if !matched && !backtrack(state, &mut at) {
+            break false;
+        }
+    };
+
+    state.choices.clear();
+    state.frames.clear();
+    state.trail.clear();
+    found
+}
+
+/// Resumes the latest choice left, or returns false if there is none.
+fn backtrack<'a>(state: &mut State<'a>, at: &mut Thread<'a>) -> bool {
+    while let Some(Backtrack {
+        choice,
+        frames,
+        trail,
+    }) = state.choices.pop()
+    {
+        state.frames.truncate(frames);
+        state.undo_captures(trail);
+
+        match choice {
+            Choice::Resume(thread) => {
+                *at = thread;
+                return true;
+            },
+            Choice::Fail => {},
+            Choice::Failed(index, repeat) => {
+                state.failed.insert((index, repeat));
+            },
+        }
+    }
+
+    state.undo_captures(0);
+    false
+}
+
+/// Advances `at` by one goal, or returns false if it doesn't match.
+fn step<'a>(state: &mut State<'a>, at: &mut Thread<'a>) -> bool {
+    match at.goal {
+        Goal::Tokens(tokens, flags) => {
+            let Some(((value, quantifier), rest)) = tokens.split_first() else {
+                at.goal = Goal::Return;
+                return true;
+            };
+
+            if !rest.is_empty() {
+                at.frame = state.push_frame(Frame::Tokens(rest, flags), at.frame);
+            }
+
+            let (min, max, greed) = match *quantifier {
+                Quantifier::Exact => {
+                    at.goal = Goal::Atom(value, flags);
+                    return true;
+                },
+                Quantifier::Star { greed } => (0, None, greed),
+                Quantifier::Plus { greed } => (1, None, greed),
+                Quantifier::Optional { greed } => (0, Some(1), greed),
+                Quantifier::Range { min, max, greed } => (min, max, greed),
+            };
+            let repeat = Repeat {
+                id: state.repeats,
+                min,
+                max,
+                lazy: greed == Greed::Lazy,
+            };
+            state.repeats += 1;
+
+            if greed == Greed::Possessive {
+                let choices = state.choices.len();
+
+                state.push_choice(Choice::Fail);
+                at.frame = state.push_frame(Frame::Cut(choices, None), at.frame);
+            }
+
+            at.goal = Goal::Repeat(value, flags, repeat);
+            true
+        },
+        Goal::Atom(value, flags) => match step_atom(state, at, value, flags) {
+            Some(end) => {
+                at.index = end;
+                true
+            },
+            None => false,
+        },
+        Goal::Repeat(value, flags, repeat) => {
+            let Repeat { min, max, lazy, .. } = repeat;
+
+            if max == Some(0) {
+                at.goal = Goal::Return;
+                return true;
+            }
+
+            if state.failed.contains(&(at.index, repeat)) {
+                return false;
+            }
+
+            if state.memoize {
+                state.push_choice(Choice::Failed(at.index, repeat));
+            }
+
+            let after = at.frame;
+            let remaining = Repeat {
+                min: min.saturating_sub(1),
+                max: max.map(|max| max - 1),
+                ..repeat
+            };
+            let frame = Frame::Repeat {
+                value,
+                flags,
+                remaining,
+                start: at.index,
+                required: min > 0,
+            };
+            let again = Thread {
+                goal: Goal::Atom(value, flags),
+                frame: state.push_frame(frame, after),
+                ..*at
+            };
+            let done = Thread {
+                goal: Goal::Return,
+                ..*at
+            };
+
+            *at = match (min, lazy) {
+                (0, true) => {
+                    state.push_choice(Choice::Resume(again));
+                    done
+                },
+                (0, false) => {
+                    state.push_choice(Choice::Resume(done));
+                    again
+                },
+                _ => again,
+            };
+            true
+        },
+        Goal::Return => {
+            let (frame, parent) = state.frames[at.frame];
+            at.frame = parent;
+            at.goal = Goal::Return;
+
+            match frame {
+                Frame::Tokens(tokens, flags) => at.goal = Goal::Tokens(tokens, flags),
+                Frame::Repeat {
+                    value,
+                    flags,
+                    remaining,
+                    start,
+                    required,
+                } => {
+                    if at.index == start && !required {
+                        return false;
+                    }
+
+                    at.goal = Goal::Repeat(value, flags, remaining);
+                },
+                Frame::CloseGroup(group, start) => state.set_capture(group, (start, at.index)),
+                Frame::Cut(choices, index) => {
+                    state.choices.truncate(choices);
+                    at.index = index.unwrap_or(at.index);
+                },
+                Frame::NegLookahead(choices) => {
+                    state.choices.truncate(choices);
+                    return false;
+                },
+            }
+
+            true
+        },
+    }
+}
+
+/// Starts matching `value` at `at`, returning the index it continues from.
+fn step_atom<'a>(
+    state: &mut State<'a>,
+    at: &mut Thread<'a>,
+    value: &'a Atom,
+    flags: Flags,
+) -> Option<usize> {
+    let index = at.index;
+    let chr = state.chars.get(index).copied();
+    state.furthest = state.furthest.max(index);
+    at.goal = Goal::Return;
+
+    match value {
+        Atom::StartAnchor => {
+            let at_line_start = flags.multi_line
+                && index
+                    .checked_sub(1)
+                    .is_some_and(|before| state.chars[before] == '\n');
+            (index == 0 || at_line_start).then_some(index)
+        },
+        Atom::EndAnchor => {
+            let at_line_end = flags.multi_line && chr == Some('\n');
+            (index == state.chars.len() || at_line_end).then_some(index)
+        },
+        Atom::TextStart => (index == 0).then_some(index),
+        Atom::TextEnd => (index == state.chars.len()).then_some(index),
+        Atom::WordBoundary | Atom::NotWordBoundary => {
+            let before = index
+                .checked_sub(1)
+                .is_some_and(|before| is_word_char(state.chars[before]));
+            let after = chr.is_some_and(is_word_char);
+
+            ((before != after) == matches!(value, Atom::WordBoundary)).then_some(index)
+        },
+        Atom::Wildcard => chr
+            .is_some_and(|chr| chr != '\n' || flags.dot_matches_newline)
+            .then_some(index + 1),
+        Atom::Char(expected) => match_chars(flags, state, index, &[*expected]),
+        Atom::Literal(expected) => match_chars(flags, state, index, expected),
+        Atom::Class(class) => chr
+            .is_some_and(|chr| class.contains(chr, flags.case_insensitive))
+            .then_some(index + 1),
+        Atom::Backref(group) => {
+            let (start, end) = state.captures[*group]?;
+            let chars = state.chars;
+
+            match_chars(flags, state, index, &chars[start..end])
+        },
+        Atom::Expr(expr) => {
+            at.goal = Goal::Tokens(&expr.tokens, expr.flags);
+            Some(index)
+        },
+        Atom::Group(group, _, expr) => {
+            at.frame = state.push_frame(Frame::CloseGroup(*group, index), at.frame);
+            at.goal = Goal::Tokens(&expr.tokens, expr.flags);
+            Some(index)
+        },
+        Atom::Alternation(branches) => {
+            let (first, rest) = branches.split_first()?;
+
+            for branch in rest.iter().rev() {
+                state.push_choice(Choice::Resume(Thread {
+                    goal: Goal::Tokens(&branch.tokens, branch.flags),
+                    ..*at
+                }));
+            }
+
+            at.goal = Goal::Tokens(&first.tokens, first.flags);
+            Some(index)
+        },
+        Atom::Lookahead(expr) | Atom::AtomicGroup(expr) => {
+            let choices = state.choices.len();
+            let restore = matches!(value, Atom::Lookahead(_)).then_some(index);
+
+            state.push_choice(Choice::Fail);
+            at.frame = state.push_frame(Frame::Cut(choices, restore), at.frame);
+            at.goal = Goal::Tokens(&expr.tokens, expr.flags);
+            Some(index)
+        },
+        Atom::NegLookahead(expr) => {
+            let choices = state.choices.len();
+
+            state.push_choice(Choice::Resume(*at));
+            at.frame = state.push_frame(Frame::NegLookahead(choices), at.frame);
+            at.goal = Goal::Tokens(&expr.tokens, expr.flags);
+            Some(index)
+        },
+    }
+}
+
+fn match_value_at_index<'a>(
+    flags: Flags,
+    state: &mut State<'a>,
+    index: usize,
+    value: &'a Atom,
+    next: Next,
+) -> bool {
+    run(state, Goal::Atom(value, flags), index, next)
+}
+
+/// Matches `expected` at `index` one char after another, returning where it
+/// ends.
+fn match_chars(flags: Flags, state: &mut State, index: usize, expected: &[char]) -> Option<usize> {
+    let equal = |(&expected, &chr): (&char, &char)| match flags.case_insensitive {
+        true => case_variants(chr).any(|chr| chr == expected),
+        false => chr == expected,
+    };
+
+    let matched = expected
+        .iter()
+        .zip(&state.chars[index..])
+        .take_while(|&pair| equal(pair))
+        .count();
+
+    state.furthest = state.furthest.max(index + matched);
+    (matched == expected.len()).then_some(index + matched)
+}
+
+/// Merges runs of adjacent unquantified chars into literals, so they are
+/// matched in one step instead of one token at a time.
+fn coalesce_literals(tokens: Vec<Token>) -> Vec<Token> {
+    let mut coalesced: Vec<Token> = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        let chars = match &token {
+            (Atom::Char(chr), Quantifier::Exact) => vec![*chr],
+            (Atom::Literal(chars), Quantifier::Exact) => chars.clone(),
+            _ => {
+                coalesced.push(token);
+                continue;
+            },
+        };
+
+        match coalesced.last_mut() {
+            Some((Atom::Literal(literal), Quantifier::Exact)) => literal.extend(chars),
+            Some((Atom::Char(chr), Quantifier::Exact)) => {
+                let literal = std::iter::once(*chr).chain(chars).collect();
+                *coalesced.last_mut().unwrap() = (Atom::Literal(literal), Quantifier::Exact);
+            },
+            _ => coalesced.push(token),
+        }
+    }
+
+    coalesced
+}
+
+fn escaped_char(chr: char) -> char {
+    match chr {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        chr => chr,
+    }
+}
+
+fn write_escaped(f: &mut Formatter<'_>, chr: char, special: impl Fn(char) -> bool) -> fmt::Result {
+    match chr {
+        '\n' => f.write_str("\\n"),
+        '\t' => f.write_str("\\t"),
+        '\r' => f.write_str("\\r"),
+        chr if chr.is_control() => write!(f, "\\x{{{:X}}}", chr as u32),
+        chr if special(chr) => write!(f, "\\{}", chr),
+        chr => write!(f, "{}", chr),
+    }
+}
+
+fn is_word_char(chr: char) -> bool {
+    #[cfg(not(feature = "unicode"))]
+    return chr.is_ascii_alphanumeric() || chr == '_';
+    #[cfg(feature = "unicode")]
+    return chr.is_alphanumeric() || chr == '_';
+}
+
+/// The ranges of word chars that `\\w` matches with the `unicode` feature,
+/// computed on first use.
+#[cfg(feature = "unicode")]
+fn unicode_word_ranges() -> &'static Vec<(char, char)> {
+    static RANGES: OnceLock<Vec<(char, char)>> = OnceLock::new();
+
+    RANGES.get_or_init(|| {
+        let mut ranges: Vec<(char, char)> = Vec::new();
+
+        for chr in (0..=char::MAX as u32).filter_map(char::from_u32) {
+            if !is_word_char(chr) {
+                continue;
+            }
+
+            match ranges.last_mut() {
+                Some((_, end)) if *end as u32 + 1 == chr as u32 => *end = chr,
+                _ => ranges.push((chr, chr)),
+            }
+        }
+
+        ranges
+    })
+}
+
+/// The decimal digits (general category Nd) of Unicode 14 that `\\d`
+/// matches with the `unicode` feature. The standard library has no way to
+/// tell them apart from other numeric chars.
+#[cfg(feature = "unicode")]
+const DECIMAL_DIGITS: &[(char, char)] = &[
+    ('\u{30}', '\u{39}'),
+    ('\u{660}', '\u{669}'),
+    ('\u{6F0}', '\u{6F9}'),
+    ('\u{7C0}', '\u{7C9}'),
+    ('\u{966}', '\u{96F}'),
+    ('\u{9E6}', '\u{9EF}'),
+    ('\u{A66}', '\u{A6F}'),
+    ('\u{AE6}', '\u{AEF}'),
+    ('\u{B66}', '\u{B6F}'),
+    ('\u{BE6}', '\u{BEF}'),
+    ('\u{C66}', '\u{C6F}'),
+    ('\u{CE6}', '\u{CEF}'),
+    ('\u{D66}', '\u{D6F}'),
+    ('\u{DE6}', '\u{DEF}'),
+    ('\u{E50}', '\u{E59}'),
+    ('\u{ED0}', '\u{ED9}'),
+    ('\u{F20}', '\u{F29}'),
+    ('\u{1040}', '\u{1049}'),
+    ('\u{1090}', '\u{1099}'),
+    ('\u{17E0}', '\u{17E9}'),
+    ('\u{1810}', '\u{1819}'),
+    ('\u{1946}', '\u{194F}'),
+    ('\u{19D0}', '\u{19D9}'),
+    ('\u{1A80}', '\u{1A89}'),
+    ('\u{1A90}', '\u{1A99}'),
+    ('\u{1B50}', '\u{1B59}'),
+    ('\u{1BB0}', '\u{1BB9}'),
+    ('\u{1C40}', '\u{1C49}'),
+    ('\u{1C50}', '\u{1C59}'),
+    ('\u{A620}', '\u{A629}'),
+    ('\u{A8D0}', '\u{A8D9}'),
+    ('\u{A900}', '\u{A909}'),
+    ('\u{A9D0}', '\u{A9D9}'),
+    ('\u{A9F0}', '\u{A9F9}'),
+    ('\u{AA50}', '\u{AA59}'),
+    ('\u{ABF0}', '\u{ABF9}'),
+    ('\u{FF10}', '\u{FF19}'),
+    ('\u{104A0}', '\u{104A9}'),
+    ('\u{10D30}', '\u{10D39}'),
+    ('\u{11066}', '\u{1106F}'),
+    ('\u{110F0}', '\u{110F9}'),
+    ('\u{11136}', '\u{1113F}'),
+    ('\u{111D0}', '\u{111D9}'),
+    ('\u{112F0}', '\u{112F9}'),
+    ('\u{11450}', '\u{11459}'),
+    ('\u{114D0}', '\u{114D9}'),
+    ('\u{11650}', '\u{11659}'),
+    ('\u{116C0}', '\u{116C9}'),
+    ('\u{11730}', '\u{11739}'),
+    ('\u{118E0}', '\u{118E9}'),
+    ('\u{11950}', '\u{11959}'),
+    ('\u{11C50}', '\u{11C59}'),
+    ('\u{11D50}', '\u{11D59}'),
+    ('\u{11DA0}', '\u{11DA9}'),
+    ('\u{16A60}', '\u{16A69}'),
+    ('\u{16AC0}', '\u{16AC9}'),
+    ('\u{16B50}', '\u{16B59}'),
+    ('\u{1D7CE}', '\u{1D7FF}'),
+    ('\u{1E140}', '\u{1E149}'),
+    ('\u{1E2F0}', '\u{1E2F9}'),
+    ('\u{1E950}', '\u{1E959}'),
+    ('\u{1FBF0}', '\u{1FBF9}'),
+];
+
+fn latin1_chars(bytes: &[u8]) -> Vec<char> {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+/// Maps every char index of `string`, and the index one past its last char,
+/// to the corresponding byte index.
+fn byte_offsets(string: &str) -> Vec<usize> {
+    string
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([string.len()])
+        .collect()
+}
+
+fn case_variants(chr: char) -> impl Iterator<Item = char> {
+    fn single(mut mapping: impl Iterator<Item = char>) -> Option<char> {
+        let first = mapping.next();
+        first.filter(|_| mapping.next().is_none())
+    }
+
+    // With the `unicode` feature, chars like `ſ` whose uppercase form
+    // lowercases to another char are folded to that one as well.
+    #[cfg(feature = "unicode")]
+    let folded = single(chr.to_uppercase()).and_then(|upper| single(upper.to_lowercase()));
+    #[cfg(not(feature = "unicode"))]
+    let folded = None;
+
+    [
+        Some(chr),
+        single(chr.to_lowercase()),
+        single(chr.to_uppercase()),
+        folded,
+    ]
+    .into_iter()
+    .flatten()
+}
+
+fn is_valid_group_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|chr| chr.is_alphabetic() || chr == '_')
+        && chars.all(|chr| chr.is_alphanumeric() || chr == '_')
+}
+
+/// Returns the index of the `\\E` ending a quote that starts at `start`. A
+/// quote without one runs to the end of the pattern.
+fn quote_end(chars: &[char], start: usize) -> usize {
+    (start..chars.len())
+        .find(|&i| chars[i..].starts_with(&['\\', 'E']))
+        .unwrap_or(chars.len())
+}
+
+/// Returns the length of a `(?#...)` comment at the start of `chars`, which
+/// ends at the first `)`.
+fn comment_len(chars: &[char]) -> Option<usize> {
+    chars.iter().position(|&chr| chr == ')').map(|len| len + 1)
+}
+
+/// Returns the index of the `)` balancing the `(` at `start`, skipping over
+/// escapes, quotes, comments and classes.
+fn find_closing_paren(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if chars.get(i + 1) == Some(&'Q') => i = quote_end(chars, i + 2) + 1,
+            '\\' => i += 1,
+            '(' if chars[i..].starts_with(&['(', '?', '#']) => {
+                i += comment_len(&chars[i..]).unwrap_or(chars.len() - i) - 1
+            },
+            '[' => {
+                if let Ok((_, end)) = parse_class(chars, i) {
+                    i = end;
+                }
+            },
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => {},
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+/// Parses the char at `i` of the class starting at `start`, which may be
+/// escaped, and returns it with the index after it.
+fn parse_class_literal(
+    chars: &[char],
+    i: usize,
+    start: usize,
+) -> Result<(char, usize), RegexpParsingError> {
+    let unterminated = || RegexpParsingError::UnterminatedClass { index: start };
+
+    match chars.get(i) {
+        Some('\\') => match chars.get(i + 1) {
+            Some(&escape @ ('x' | 'u')) => parse_code_point(escape, &chars[(i + 2)..])
+                .map(|(chr, len)| (chr, i + 2 + len))
+                .ok_or(RegexpParsingError::InvalidEscape { index: i }),
+            Some(&chr) => Ok((escaped_char(chr), i + 2)),
+            None => Err(unterminated()),
+        },
+        Some(&chr) => Ok((chr, i + 1)),
+        None => Err(unterminated()),
+    }
+}
+
+fn parse_class(chars: &[char], start: usize) -> Result<(CharClass, usize), RegexpParsingError> {
+    let literal_at = |i: usize| parse_class_literal(chars, i, start);
+
+    let negated = chars.get(start + 1) == Some(&'^');
+    let mut ranges = Vec::new();
+    let mut i = start + 1 + negated as usize;
+
+    loop {
+        if chars.get(i) == Some(&']') {
+            if ranges.is_empty() {
+                return Err(RegexpParsingError::EmptyClass { index: start });
+            }
+
+            return Ok((CharClass { ranges, negated }, i));
+        }
+
+        if chars[i..].starts_with(&['[', ':']) {
+            let len = chars[(i + 2)..]
+                .iter()
+                .take_while(|chr| chr.is_ascii_alphabetic())
+                .count();
+
+            if chars[(i + 2 + len)..].starts_with(&[':', ']']) {
+                let name: String = chars[(i + 2)..(i + 2 + len)].iter().collect();
+                let Some(class) = CharClass::posix(&name) else {
+                    return Err(RegexpParsingError::UnknownPosixClass { index: i });
+                };
+
+                ranges.extend(class);
+                i += len + 4;
+
+                continue;
+            }
+        }
+
+        if chars.get(i) == Some(&'\\') {
+            if let Some(class) = chars.get(i + 1).and_then(|&chr| CharClass::escaped(chr)) {
+                ranges.extend(class.into_ranges());
+                i += 2;
+
+                continue;
+            }
+        }
+
+        let first_start = i;
+        let (first, after_first) = literal_at(i)?;
+        i = after_first;
+
+        match (chars.get(i), chars.get(i + 1)) {
+            (Some('-'), Some(&next)) if next != ']' => {
+                let (last, after_last) = literal_at(i + 1)?;
+
+                if first > last {
+                    return Err(RegexpParsingError::InvalidClassRange { index: first_start });
+                }
+
+                ranges.push((first, last));
+                i = after_last;
+            },
+            _ => ranges.push((first, first)),
+        }
+    }
+}
+
+/// Parses the code point following a `\\x` or `\\u` escape, given as up to six
+/// hex digits in braces, or for `\\x` also as exactly two without them. Returns
+/// the char and how many chars it spans.
+fn parse_code_point(escape: char, chars: &[char]) -> Option<(char, usize)> {
+    let (digits, len) = match chars {
+        ['{', rest @ ..] => {
+            let end = rest.iter().position(|&chr| chr == '}')?;
+            (&rest[..end], end + 2)
+        },
+        [_, _, ..] if escape == 'x' => (&chars[..2], 2),
+        _ => return None,
+    };
+
+    if digits.is_empty() || digits.len() > 6 || !digits.iter().all(char::is_ascii_hexdigit) {
+        return None;
+    }
+
+    let digits: String = digits.iter().collect();
+    let code = u32::from_str_radix(&digits, 16).ok()?;
+
+    char::from_u32(code).map(|chr| (chr, len))
+}
+
+/// Applies the flags of an inline group like `(?i-m:`, given the chars after
+/// the `?`, to `flags`. Stops at a `:` and returns the new flags and how many
+/// chars came before it, or the position of the first char that isn't a flag.
+fn parse_flags(chars: &[char], mut flags: Flags) -> Result<(Flags, usize), usize> {
+    let mut enable = true;
+
+    for (i, &chr) in chars.iter().enumerate() {
+        match chr {
+            ':' => return Ok((flags, i)),
+            '-' if enable => enable = false,
+            'i' => flags.case_insensitive = enable,
+            'm' => flags.multi_line = enable,
+            's' => flags.dot_matches_newline = enable,
+            'x' => flags.ignore_whitespace = enable,
+            _ => return Err(i),
+        }
+    }
+
+    Ok((flags, chars.len()))
+}
+
+/// Writes the inline flags that turn `from` into `to`, like `i-m`.
+fn flag_changes(from: Flags, to: Flags) -> String {
+    let flags = [
+        ('i', from.case_insensitive, to.case_insensitive),
+        ('m', from.multi_line, to.multi_line),
+        ('s', from.dot_matches_newline, to.dot_matches_newline),
+        ('x', from.ignore_whitespace, to.ignore_whitespace),
+    ];
+    let enabled = flags.iter().filter(|&&(_, from, to)| !from && to);
+    let disabled = flags.iter().filter(|&&(_, from, to)| from && !to);
+    let mut changes: String = enabled.map(|&(flag, ..)| flag).collect();
+
+    if disabled.clone().next().is_some() {
+        changes.push('-');
+        changes.extend(disabled.map(|&(flag, ..)| flag));
+    }
+
+    changes
+}
+
+fn parse_range(chars: &[char]) -> Option<(usize, Option<usize>, usize)> {
+    let len = chars.iter().position(|&chr| chr == '}')?;
+    let body: String = chars[..len].iter().collect();
+    let parse = |digits: &str| match digits.chars().all(|chr| chr.is_ascii_digit()) {
+        true => digits.parse().ok(),
+        false => None,
+    };
+
+    match body.split_once(',') {
+        Some((min, "")) => Some((parse(min)?, None, len)),
+        Some((min, max)) => Some((parse(min)?, Some(parse(max)?), len)),
+        None => {
+            let count = parse(&body)?;
+            Some((count, Some(count), len))
+        },
+    }
+}
+
+type Token = (Atom, Quantifier);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Atom {
+    StartAnchor,
+    EndAnchor,
+    /// Only matches at the start of the text, even in multi-line mode.
+    TextStart,
+    /// Only matches at the end of the text, even in multi-line mode.
+    TextEnd,
+    WordBoundary,
+    NotWordBoundary,
+    Wildcard,
+    Char(char),
+    /// A run of chars matched one after another, see [`coalesce_literals`].
+    Literal(Vec<char>),
+    Class(CharClass),
+    Expr(Regexp),
+    Group(usize, Option<String>, Regexp),
+    Alternation(Vec<Regexp>),
+    Lookahead(Regexp),
+    NegLookahead(Regexp),
+    AtomicGroup(Regexp),
+    Backref(usize),
+}
+
+impl Atom {
+    /// Returns the fewest and the most chars the atom can match.
+    fn len_bounds(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Wildcard | Self::Char(_) | Self::Class(_) => (1, Some(1)),
+            Self::Literal(chars) => (chars.len(), Some(chars.len())),
+            Self::Expr(expr) | Self::Group(_, _, expr) | Self::AtomicGroup(expr) => {
+                expr.len_bounds()
+            },
+            Self::Alternation(branches) => branches
+                .iter()
+                .map(Regexp::len_bounds)
+                .reduce(|(min, max), (branch_min, branch_max)| {
+                    (
+                        min.min(branch_min),
+                        max.zip(branch_max).map(|(a, b)| a.max(b)),
+                    )
+                })
+                .unwrap_or((0, Some(0))),
+            Self::Backref(_) => (0, None),
+            _ => (0, Some(0)),
+        }
+    }
+
+    fn sub_exprs(&self) -> &[Regexp] {
+        match self {
+            Self::Expr(expr)
+            | Self::Group(_, _, expr)
+            | Self::Lookahead(expr)
+            | Self::NegLookahead(expr)
+            | Self::AtomicGroup(expr) => std::slice::from_ref(expr),
+            Self::Alternation(branches) => branches,
+            _ => &[],
+        }
+    }
+
+    fn sub_exprs_mut(&mut self) -> &mut [Regexp] {
+        match self {
+            Self::Expr(expr)
+            | Self::Group(_, _, expr)
+            | Self::Lookahead(expr)
+            | Self::NegLookahead(expr)
+            | Self::AtomicGroup(expr) => std::slice::from_mut(expr),
+            Self::Alternation(branches) => branches,
+            _ => &mut [],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Flags {
+    case_insensitive: bool,
+    dot_matches_newline: bool,
+    multi_line: bool,
+    ignore_whitespace: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CharClass {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl CharClass {
+    fn escaped(chr: char) -> Option<Self> {
+        let ranges = match chr.to_ascii_lowercase() {
+            #[cfg(not(feature = "unicode"))]
+            'd' => vec![('0', '9')],
+            #[cfg(not(feature = "unicode"))]
+            'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+            #[cfg(feature = "unicode")]
+            'd' => DECIMAL_DIGITS.to_vec(),
+            #[cfg(feature = "unicode")]
+            'w' => unicode_word_ranges().clone(),
+            's' => vec![('\t', '\r'), (' ', ' ')],
+            _ => return None,
+        };
+
+        Some(Self {
+            ranges,
+            negated: chr.is_ascii_uppercase(),
+        })
+    }
+
+    /// Returns the ASCII ranges of a POSIX class like `[:alpha:]` by name.
+    fn posix(name: &str) -> Option<Vec<(char, char)>> {
+        let ranges = match name {
+            "alnum" => vec![('0', '9'), ('A', 'Z'), ('a', 'z')],
+            "alpha" => vec![('A', 'Z'), ('a', 'z')],
+            "blank" => vec![('\t', '\t'), (' ', ' ')],
+            "cntrl" => vec![('\0', '\x1f'), ('\x7f', '\x7f')],
+            "digit" => vec![('0', '9')],
+            "graph" => vec![('!', '~')],
+            "lower" => vec![('a', 'z')],
+            "print" => vec![(' ', '~')],
+            "punct" => vec![('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "space" => vec![('\t', '\r'), (' ', ' ')],
+            "upper" => vec![('A', 'Z')],
+            "xdigit" => vec![('0', '9'), ('A', 'F'), ('a', 'f')],
+            _ => return None,
+        };
+
+        Some(ranges)
+    }
+
+    fn into_ranges(self) -> Vec<(char, char)> {
+        if !self.negated {
+            return self.ranges;
+        }
+
+        let mut ranges = self.ranges;
+        let mut complement = Vec::new();
+        let mut first = Some('\0');
+
+        ranges.sort();
+
+        for (start, end) in ranges {
+            match first {
+                Some(chr) if chr < start => {
+                    let before = char::from_u32(start as u32 - 1).unwrap_or('\u{D7FF}');
+                    complement.push((chr, before));
+                },
+                None => break,
+                _ => {},
+            }
+
+            first = match end {
+                char::MAX => None,
+                '\u{D7FF}' => Some('\u{E000}'),
+                end => char::from_u32(end as u32 + 1),
+            };
+        }
+
+        if let Some(chr) = first {
+            complement.push((chr, char::MAX));
+        }
+
+        complement
+    }
+
+    fn contains(&self, chr: char, case_insensitive: bool) -> bool {
+        let in_ranges = |chr| {
+            self.ranges
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&chr))
+        };
+        let in_ranges = match case_insensitive {
+            true => case_variants(chr).any(in_ranges),
+            false => in_ranges(chr),
+        };
+
+        in_ranges != self.negated
+    }
+}
+
+impl Display for CharClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let special = |chr| "\\[]^-".contains(chr);
+
+        f.write_str(if self.negated { "[^" } else { "[" })?;
+
+        for &(start, end) in &self.ranges {
+            write_escaped(f, start, special)?;
+
+            if start != end {
+                f.write_str("-")?;
+                write_escaped(f, end, special)?;
+            }
+        }
+
+        f.write_str("]")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Quantifier {
+    Exact,
+    Star {
+        greed: Greed,
+    },
+    Plus {
+        greed: Greed,
+    },
+    Optional {
+        greed: Greed,
+    },
+    Range {
+        min: usize,
+        max: Option<usize>,
+        greed: Greed,
+    },
+}
+
+impl Quantifier {
+    /// Returns the fewest and the most repetitions allowed.
+    fn bounds(&self) -> (usize, Option<usize>) {
+        match *self {
+            Self::Exact => (1, Some(1)),
+            Self::Star { .. } => (0, None),
+            Self::Plus { .. } => (1, None),
+            Self::Optional { .. } => (0, Some(1)),
+            Self::Range { min, max, .. } => (min, max),
+        }
+    }
+
+    fn greed(&self) -> Greed {
+        match *self {
+            Self::Exact => Greed::Greedy,
+            Self::Star { greed }
+            | Self::Plus { greed }
+            | Self::Optional { greed }
+            | Self::Range { greed, .. } => greed,
+        }
+    }
+
+    fn with_greed(self, greed: Greed) -> Self {
+        match self {
+            Self::Star { .. } => Self::Star { greed },
+            Self::Plus { .. } => Self::Plus { greed },
+            Self::Optional { .. } => Self::Optional { greed },
+            Self::Range { min, max, .. } => Self::Range { min, max, greed },
+            quantifier => quantifier,
+        }
+    }
+}
+
+impl Display for Quantifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Exact => Ok(()),
+            Self::Star { greed } => write!(f, "*{}", greed),
+            Self::Plus { greed } => write!(f, "+{}", greed),
+            Self::Optional { greed } => write!(f, "?{}", greed),
+            Self::Range {
+                min,
+                max: None,
+                greed,
+            } => write!(f, "{{{},}}{}", min, greed),
+            Self::Range {
+                min,
+                max: Some(max),
+                greed,
+            } if min == max => write!(f, "{{{}}}{}", min, greed),
+            Self::Range {
+                min,
+                max: Some(max),
+                greed,
+            } => write!(f, "{{{},{}}}{}", min, max, greed),
+        }
+    }
+}
+
+/// How a quantifier chooses between more and fewer repetitions: greedy ones
+/// prefer more, lazy ones fewer, and possessive ones take as many as possible
+/// without ever giving any back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Greed {
+    Greedy,
+    Lazy,
+    Possessive,
+}
+
+impl Display for Greed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Greedy => "",
+            Self::Lazy => "?",
+            Self::Possessive => "+",
+        })
+    }
+}
+
+#[test]
+fn test() {
+    let expr0 = Regexp::new("ab.?c").unwrap();
+    let expr1 = Regexp::new("a+b*\\.").unwrap();
+    let expr2 = Regexp::new("a.*b").unwrap();
+
+    assert!(expr0.matches("abc"));
+    assert!(expr0.matches("abdc"));
+    assert!(!expr0.matches("abcde"));
+
+    assert!(expr1.matches("abbb."));
+    assert!(expr1.matches("aaaa."));
+    assert!(!expr1.matches("b."));
+    assert!(!expr1.matches("ab!"));
+
+    assert!(expr2.matches("asadf.b"));
+    assert!(expr2.matches("ab"));
+}
+
+#[test]
+fn anchors() {
+    let expr0 = Regexp::new("^a").unwrap();
+    let expr1 = Regexp::new("a$").unwrap();
+    let expr2 = Regexp::new("^$").unwrap();
+    let expr3 = Regexp::new("\\^a\\$").unwrap();
+
+    assert!(expr0.matches("a"));
+    assert!(!expr0.matches(""));
+    assert!(!expr0.matches("ba"));
+
+    assert!(expr1.matches("a"));
+    assert!(!expr1.matches(""));
+    assert!(!expr1.matches("ab"));
+
+    assert!(expr2.matches(""));
+    assert!(!expr2.matches("a"));
+
+    assert!(expr3.matches("^a$"));
+    assert!(!expr3.matches("a"));
+}
+
+#[test]
+fn ranges() {
+    let expr0 = Regexp::new("a{3}").unwrap();
+    let expr1 = Regexp::new("a{2,}b").unwrap();
+    let expr2 = Regexp::new("xa{1,4}a").unwrap();
+    let expr3 = Regexp::new("ba{0}c").unwrap();
+    let expr4 = Regexp::new("ba{0,0}c").unwrap();
+    let expr5 = Regexp::new("a{x}").unwrap();
+
+    assert!(expr0.matches("aaa"));
+    assert!(!expr0.matches("aa"));
+    assert!(!expr0.matches("aaaa"));
+
+    assert!(expr1.matches("aab"));
+    assert!(expr1.matches("aaaaab"));
+    assert!(!expr1.matches("ab"));
+
+    assert!(expr2.matches("xaa"));
+    assert!(expr2.matches("xaaaaa"));
+    assert!(!expr2.matches("xa"));
+    assert!(!expr2.matches("xaaaaaa"));
+
+    assert!(expr3.matches("bc"));
+    assert!(!expr3.matches("bac"));
+    assert!(expr4.matches("bc"));
+
+    assert!(expr5.matches("a{x}"));
+
+    assert!(Regexp::new("a{2,1}").is_err());
+}
+
+#[test]
+fn alternation() {
+    let expr0 = Regexp::new("cat|dog").unwrap();
+    let expr1 = Regexp::new("(ab|cd)e").unwrap();
+    let expr2 = Regexp::new("a|").unwrap();
+    let expr3 = Regexp::new("a\\|b").unwrap();
+    let expr4 = Regexp::new("(a|ab)c").unwrap();
+
+    assert!(expr0.matches("cat"));
+    assert!(expr0.matches("dog"));
+    assert!(!expr0.matches("cow"));
+    assert!(!expr0.matches("catdog"));
+
+    assert!(expr1.matches("abe"));
+    assert!(expr1.matches("cde"));
+    assert!(!expr1.matches("ace"));
+    assert!(!expr1.matches("ab"));
+
+    assert!(expr2.matches("a"));
+    assert!(!expr2.matches("b"));
+    assert!(expr2.matches(""));
+
+    assert!(expr3.matches("a|b"));
+    assert!(!expr3.matches("a"));
+
+    assert!(expr4.matches("abc"));
+    assert!(expr4.matches("ac"));
+    assert!(!expr4.matches("abac"));
+}
+
+#[test]
+fn classes() {
+    let expr0 = Regexp::new("[abc]{1,}").unwrap();
+    let expr1 = Regexp::new("[a-z0-9]{0,}").unwrap();
+    let expr2 = Regexp::new("[-a][a-]").unwrap();
+    let expr3 = Regexp::new("[\\]x]").unwrap();
+    let expr4 = Regexp::new("[a|b]").unwrap();
+
+    assert!(expr0.matches("abcba"));
+    assert!(!expr0.matches("abd"));
+
+    assert!(expr1.matches("hello42"));
+    assert!(expr1.matches(""));
+    assert!(!expr1.matches("Hello"));
+
+    assert!(expr2.matches("--"));
+    assert!(expr2.matches("aa"));
+    assert!(!expr2.matches("ab"));
+
+    assert!(expr3.matches("]"));
+    assert!(expr3.matches("x"));
+    assert!(!expr3.matches("\\"));
+
+    assert!(expr4.matches("|"));
+    assert!(!expr4.matches("ab"));
+
+    assert_eq!(
+        Regexp::new("ab[cd").unwrap_err(),
+        RegexpParsingError::UnterminatedClass { index: 2 }
+    );
+    assert!(Regexp::new("[z-a]").is_err());
+}
+
+#[test]
+fn negated_classes() {
+    let expr0 = Regexp::new("[^abc]").unwrap();
+    let expr1 = Regexp::new("[^0-9]{1,}").unwrap();
+    let expr2 = Regexp::new("[\\^][a^]").unwrap();
+
+    assert!(expr0.matches("d"));
+    assert!(!expr0.matches("a"));
+    assert!(!expr0.matches(""));
+
+    assert!(expr1.matches("abc"));
+    assert!(!expr1.matches("ab1"));
+
+    assert!(expr2.matches("^^"));
+    assert!(expr2.matches("^a"));
+    assert!(!expr2.matches("a^"));
+
+    assert!(Regexp::new("[]").is_err());
+    assert!(Regexp::new("[^]").is_err());
+}
+
+#[test]
+fn escape_classes() {
+    let expr0 = Regexp::new("\\d{1,}").unwrap();
+    let expr1 = Regexp::new("a\\sb").unwrap();
+    let expr2 = Regexp::new("\\w{1,}").unwrap();
+    let expr3 = Regexp::new("[\\d-]{1,}").unwrap();
+    let expr4 = Regexp::new("\\.").unwrap();
+
+    assert!(expr0.matches("2024"));
+    assert!(!expr0.matches(""));
+    assert!(!expr0.matches("20x4"));
+
+    assert!(expr1.matches("a b"));
+    assert!(expr1.matches("a\tb"));
+    assert!(!expr1.matches("ab"));
+
+    assert!(expr2.matches("snake_Case42"));
+    assert!(!expr2.matches("kebab-case"));
+
+    assert!(expr3.matches("555-0100"));
+    assert!(!expr3.matches("555 0100"));
+
+    assert!(expr4.matches("."));
+    assert!(!expr4.matches("x"));
+}
+
+#[test]
+fn negated_escape_classes() {
+    let expr0 = Regexp::new("\\D").unwrap();
+    let expr1 = Regexp::new("\\D{1,}").unwrap();
+    let expr2 = Regexp::new("\\W\\S").unwrap();
+    let expr3 = Regexp::new("[\\D5]{1,}").unwrap();
+
+    assert!(!expr0.matches("5"));
+    assert!(expr0.matches("x"));
+    assert!(!expr0.matches("xy"));
+
+    assert!(expr1.matches("abc!"));
+    assert!(!expr1.matches("ab1"));
+
+    assert!(expr2.matches("-x"));
+    assert!(!expr2.matches("_x"));
+    assert!(!expr2.matches("- "));
+
+    assert!(expr3.matches("a5b"));
+    assert!(!expr3.matches("a4b"));
+}
+
+#[test]
+fn find() {
+    let expr0 = Regexp::new("b.").unwrap();
+    let expr1 = Regexp::new("\\d{1,}").unwrap();
+    let expr2 = Regexp::new("^a").unwrap();
+    let expr3 = Regexp::new("x*").unwrap();
+
+    assert_eq!(expr0.find("aabcc"), Some((2, 4)));
+    assert_eq!(expr0.find("aab"), None);
+
+    assert_eq!(expr1.find("ab123c45"), Some((2, 5)));
+    assert_eq!(expr1.find("añ7"), Some((2, 3)));
+
+    assert_eq!(expr2.find("aa"), Some((0, 1)));
+    assert_eq!(expr2.find("ba"), None);
+
+    assert_eq!(expr3.find("abc"), Some((0, 0)));
+}
+
+#[test]
+fn find_iter() {
+    let expr0 = Regexp::new("\\d{1,}").unwrap();
+    let expr1 = Regexp::new("a{0,}").unwrap();
+
+    assert_eq!(
+        expr0.find_iter("a12b345").collect::<Vec<_>>(),
+        [(1, 3), (4, 7)]
+    );
+    assert_eq!(expr0.find_iter("abc").count(), 0);
+
+    assert_eq!(
+        expr1.find_iter("baa").collect::<Vec<_>>(),
+        [(0, 0), (1, 3), (3, 3)]
+    );
+    assert_eq!(expr1.find_iter("").collect::<Vec<_>>(), [(0, 0)]);
+}
+
+#[test]
+fn captures() {
+    let expr0 = Regexp::new("(\\d{1,})-\\d{1,}").unwrap();
+    let expr1 = Regexp::new("(a)x|(b)x").unwrap();
+
+    let some = |groups: &[&str]| {
+        Some(
+            groups
+                .iter()
+                .map(|group| Some(group.to_string()))
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    assert_eq!(expr0.captures("12-34"), some(&["12-34", "12"]));
+    assert_eq!(expr0.captures("tel 555-0100."), some(&["555-0100", "555"]));
+    assert_eq!(expr0.captures("a-b"), None);
+
+    assert_eq!(
+        expr1.captures("bx"),
+        Some(vec![Some("bx".to_string()), None, Some("b".to_string())])
+    );
+    assert_eq!(
+        expr1.captures("ax"),
+        Some(vec![Some("ax".to_string()), Some("a".to_string()), None])
+    );
+}
+
+#[test]
+fn named_captures() {
+    let expr0 = Regexp::new("(?<a>x)y|(?<b>z)y").unwrap();
+    let expr1 = Regexp::new("(?<year>\\d{1,})-\\d{1,}").unwrap();
+
+    let captures = expr0.captures_named("zy").unwrap();
+
+    assert_eq!(expr0.name_to_index("a"), Some(1));
+    assert_eq!(expr0.name_to_index("b"), Some(2));
+    assert_eq!(expr0.name_to_index("c"), None);
+    assert_eq!(captures["a"], None);
+    assert_eq!(captures["b"].as_deref(), Some("z"));
+    assert_eq!(captures.len(), 2);
+
+    assert_eq!(expr1.name_to_index("year"), Some(1));
+    assert_eq!(
+        expr1.captures("2024-10").unwrap()[1].as_deref(),
+        Some("2024")
+    );
+
+    assert!(Regexp::new("(?<a").is_err());
+    assert!(Regexp::new("(?<ax)").is_err());
+    assert!(Regexp::new("(?<>x)").is_err());
+    assert!(Regexp::new("(?<a>x)(?<a>y)").is_err());
+}
+
+#[test]
+fn case_insensitive() {
+    let expr0 = Regexp::new_case_insensitive("Hello").unwrap();
+    let expr1 = Regexp::new_case_insensitive("[a-z]{1,}").unwrap();
+    let expr2 = Regexp::new_case_insensitive("[^a]").unwrap();
+    let expr3 = Regexp::new_case_insensitive("straße").unwrap();
+
+    assert!(expr0.matches("HELLO"));
+    assert!(expr0.matches("hello"));
+    assert!(!expr0.matches("help"));
+    assert!(!Regexp::new("Hello").unwrap().matches("HELLO"));
+
+    assert!(expr1.matches("ABC"));
+    assert!(expr1.matches("aBc"));
+    assert!(!expr1.matches("AB1"));
+
+    assert!(!expr2.matches("A"));
+    assert!(expr2.matches("B"));
+
+    assert!(expr3.matches("STRAßE"));
+    assert!(!expr3.matches("STRASSE"));
+}
+
+#[test]
+fn lazy_quantifiers() {
+    let greedy = Regexp::new("a.*b").unwrap();
+    let lazy = Regexp::new("a.*?b").unwrap();
+
+    assert_eq!(greedy.find("axbxb"), Some((0, 5)));
+    assert_eq!(lazy.find("axbxb"), Some((0, 3)));
+    assert!(lazy.matches("axbxb"));
+
+    let greedy = Regexp::new("<.+>").unwrap();
+    let lazy = Regexp::new("<.+?>").unwrap();
+
+    assert_eq!(greedy.find("<a><b>"), Some((0, 6)));
+    assert_eq!(lazy.find("<a><b>"), Some((0, 3)));
+    assert_eq!(lazy.find("<>"), None);
+}
+
+#[test]
+fn plus() {
+    let expr0 = Regexp::new("x[ab]+y").unwrap();
+    let expr1 = Regexp::new("a+").unwrap();
+
+    assert!(expr0.matches("xabay"));
+    assert!(expr0.matches("xby"));
+    assert!(!expr0.matches("xy"));
+    assert!(!expr0.matches("xacy"));
+
+    assert!(expr1.matches("aaa"));
+    assert!(!expr1.matches(""));
+    assert_eq!(expr1.find("baab"), Some((1, 3)));
+    assert_eq!(Regexp::new("a+?").unwrap().find("baab"), Some((1, 2)));
+}
+
+#[test]
+fn group_after_tokens() {
+    let expr0 = Regexp::new("x(ab)y").unwrap();
+    let expr1 = Regexp::new("ab(ab)c").unwrap();
+
+    assert!(expr0.matches("xaby"));
+    assert!(!expr0.matches("abxy"));
+    assert!(!expr0.matches("xyab"));
+
+    assert!(expr1.matches("ababc"));
+    assert!(!expr1.matches("abc"));
+}
+
+#[test]
+fn trailing_group_consumes_rest() {
+    let expr0 = Regexp::new("a(.{0,})").unwrap();
+    let expr1 = Regexp::new("(abc)").unwrap();
+    let expr2 = Regexp::new("a(bc)").unwrap();
+
+    assert!(expr0.matches("abcd"));
+    assert!(expr0.matches("a"));
+    assert_eq!(expr0.captures("abcd").unwrap()[1].as_deref(), Some("bcd"));
+
+    assert!(expr1.matches("abc"));
+    assert!(!expr1.matches("abcd"));
+
+    assert!(expr2.matches("abc"));
+    assert!(!expr2.matches("axc"));
+    assert!(!expr2.matches("ab"));
+    assert_eq!(expr2.find("xabc"), Some((1, 4)));
+}
+
+#[test]
+fn star_scans_from_cursor() {
+    let expr0 = Regexp::new("ab*c").unwrap();
+    let expr1 = Regexp::new("xyzab*c").unwrap();
+    let expr2 = Regexp::new("a.*b.*c").unwrap();
+
+    assert!(expr0.matches("abbc"));
+    assert!(expr0.matches("ac"));
+    assert!(!expr0.matches("aXbbc"));
+    assert!(!expr0.matches("abXbc"));
+
+    assert!(expr1.matches("xyzabbbc"));
+    assert!(expr1.matches("xyzac"));
+    assert!(!expr1.matches("xyzaXbc"));
+
+    assert!(expr2.matches("aXbYc"));
+    assert!(!expr2.matches("aXcYb"));
+}
+
+#[test]
+fn replace() {
+    let expr0 = Regexp::new("\\d+").unwrap();
+    let expr1 = Regexp::new("é+").unwrap();
+
+    assert_eq!(expr0.replace("a12b", "#"), "a#b");
+    assert_eq!(expr0.replace("a12b34", "#"), "a#b34");
+    assert_eq!(expr0.replace("abc", "#"), "abc");
+
+    assert_eq!(expr1.replace("cafééé!", "e"), "cafe!");
+}
+
+#[test]
+fn replace_all() {
+    let expr0 = Regexp::new("a").unwrap();
+    let expr1 = Regexp::new("x*").unwrap();
+    let expr2 = Regexp::new("\\d+").unwrap();
+
+    assert_eq!(expr0.replace_all("banana", "o"), "bonono");
+    assert_eq!(expr0.replace_all("xyz", "o"), "xyz");
+
+    assert_eq!(expr1.replace_all("abc", "-"), "-a-b-c-");
+    assert_eq!(expr1.replace_all("", "-"), "-");
+
+    assert_eq!(expr2.replace_all("a1b22c333", "#"), "a#b#c#");
+}
+
+#[test]
+fn replace_with() {
+    let expr0 = Regexp::new("\\d+").unwrap();
+    let expr1 = Regexp::new("\\w+").unwrap();
+    let double = |number: &str| (number.parse::<u32>().unwrap() * 2).to_string();
+
+    assert_eq!(expr0.replace_with("a5b10", double), "a10b20");
+    assert_eq!(expr0.replace_with("ab", double), "ab");
+
+    assert_eq!(
+        expr1.replace_with("hello big world", |word| word.len().to_string()),
+        "5 3 5"
+    );
+}
+
+#[test]
+fn split() {
+    let expr0 = Regexp::new("\\d+").unwrap();
+    let expr1 = Regexp::new(",").unwrap();
+    let expr2 = Regexp::new("x*").unwrap();
+
+    assert_eq!(expr0.split("a1b22c"), ["a", "b", "c"]);
+    assert_eq!(expr0.split("1a2"), ["", "a", ""]);
+    assert_eq!(expr0.split("abc"), ["abc"]);
+
+    assert_eq!(expr1.split("a,,b,"), ["a", "", "b", ""]);
+    assert_eq!(expr1.split(""), [""]);
+
+    assert_eq!(expr2.split("ab"), ["", "a", "b", ""]);
+}
+
+#[test]
+fn word_boundaries() {
+    let expr0 = Regexp::new("\\bword\\b").unwrap();
+    let expr1 = Regexp::new("\\Bor\\B").unwrap();
+    let expr2 = Regexp::new("\\b").unwrap();
+
+    assert_eq!(expr0.find("a word here"), Some((2, 6)));
+    assert_eq!(expr0.find("sword"), None);
+    assert_eq!(expr0.find("words"), None);
+    assert_eq!(expr0.find("word"), Some((0, 4)));
+    assert!(expr0.matches("word"));
+
+    assert_eq!(expr1.find("a word"), Some((3, 5)));
+    assert_eq!(expr1.find("or"), None);
+
+    assert_eq!(
+        expr2.find_iter("ab cd").collect::<Vec<_>>(),
+        [(0, 0), (2, 2), (3, 3), (5, 5)]
+    );
+    assert_eq!(expr2.find(""), None);
+}
+
+#[test]
+fn control_escapes() {
+    let expr0 = Regexp::new("a\\nb").unwrap();
+    let expr1 = Regexp::new("\\t\\r").unwrap();
+    let expr2 = Regexp::new("[\\n\\t]+").unwrap();
+    let expr3 = Regexp::new("\\.\\*").unwrap();
+
+    assert!(expr0.matches("a\nb"));
+    assert!(!expr0.matches("anb"));
+
+    assert!(expr1.matches("\t\r"));
+    assert!(!expr1.matches("tr"));
+
+    assert!(expr2.matches("\n\t\n"));
+    assert!(!expr2.matches("nt"));
+
+    assert!(expr3.matches(".*"));
+}
+
+#[test]
+fn escaped_backslash() {
+    let expr0 = Regexp::new("\\\\").unwrap();
+    let expr1 = Regexp::new("\\\\d").unwrap();
+    let expr2 = Regexp::new("a\\\\.").unwrap();
+    let expr3 = Regexp::new("\\\\\\.").unwrap();
+
+    assert!(expr0.matches("\\"));
+    assert!(!expr0.matches("\\\\"));
+
+    assert!(expr1.matches("\\d"));
+    assert!(!expr1.matches("5"));
+
+    assert!(expr2.matches("a\\x"));
+    assert!(!expr2.matches("a."));
+
+    assert!(expr3.matches("\\."));
+    assert!(!expr3.matches("\\x"));
+}
+
+#[test]
+fn error_positions() {
+    let error = Regexp::new("ab(cd").unwrap_err();
+
+    assert_eq!(error, RegexpParsingError::UnclosedParen { index: 2 });
+    assert_eq!(error.index(), 2);
+    assert_eq!(error.to_string(), "unclosed parenthesis at index 2");
+
+    assert_eq!(
+        Regexp::new("a(b(?<x>[c)d)").unwrap_err(),
+        RegexpParsingError::UnterminatedClass { index: 8 }
+    );
+    assert_eq!(
+        Regexp::new("x(a{3,1})").unwrap_err(),
+        RegexpParsingError::InvalidQuantifier { index: 3 }
+    );
+    assert_eq!(
+        Regexp::new("[a-c][z-a]").unwrap_err().to_string(),
+        "invalid character class range at index 6"
+    );
+    assert_eq!(
+        Regexp::new("(?<n>a)(?<n>b)").unwrap_err(),
+        RegexpParsingError::DuplicateGroupName { index: 7 }
+    );
+}
+
+#[test]
+fn trailing_backslash() {
+    assert_eq!(
+        Regexp::new("ab\\").unwrap_err(),
+        RegexpParsingError::TrailingBackslash { index: 2 }
+    );
+    assert_eq!(
+        Regexp::new("(a\\)").unwrap_err(),
+        RegexpParsingError::UnclosedParen { index: 0 }
+    );
+    assert_eq!(
+        Regexp::new("(a)b\\").unwrap_err(),
+        RegexpParsingError::TrailingBackslash { index: 4 }
+    );
+    assert!(Regexp::new("ab\\\\").is_ok());
+}
+
+#[test]
+fn non_capturing_groups() {
+    let expr0 = Regexp::new("(?:ab)+(c)").unwrap();
+    let expr1 = Regexp::new("(ab)+(c)").unwrap();
+    let expr2 = Regexp::new("(?:(a)|b)(?<x>c)").unwrap();
+
+    assert_eq!(
+        expr0.captures("ababc"),
+        Some(vec![Some("ababc".to_string()), Some("c".to_string())])
+    );
+    assert!(expr0.matches("abc"));
+    assert!(!expr0.matches("c"));
+    assert!(!expr0.matches("abac"));
+
+    for input in ["abc", "ababc", "c", "abac", "ab"] {
+        assert_eq!(expr0.matches(input), expr1.matches(input));
+    }
+
+    assert_eq!(expr2.name_to_index("x"), Some(2));
+    assert_eq!(expr2.captures("ac").unwrap()[1].as_deref(), Some("a"));
+}
+
+#[test]
+fn builder() {
+    let expr0 = RegexpBuilder::new()
+        .case_insensitive(true)
+        .dot_matches_newline(true)
+        .build("a.b")
+        .unwrap();
+    let expr1 = RegexpBuilder::new().multi_line(true).build("^b$").unwrap();
+    let expr2 = RegexpBuilder::new().build("(?<x>Ab)").unwrap();
+
+    assert!(expr0.matches("A\nB"));
+    assert!(expr0.matches("axb"));
+    assert!(!expr0.matches("ab"));
+
+    assert_eq!(expr1.find("a\nb\nc"), Some((2, 3)));
+    assert_eq!(Regexp::new("^b$").unwrap().find("a\nb\nc"), None);
+    assert!(!expr1.matches(""));
+
+    assert_eq!(expr2, Regexp::new("(?<x>Ab)").unwrap());
+    assert!(RegexpBuilder::new().build("(").is_err());
+}
+
+#[test]
+fn dot_matches_newline() {
+    let expr0 = Regexp::new("a.b").unwrap();
+    let expr1 = RegexpBuilder::new()
+        .dot_matches_newline(true)
+        .build("a.b")
+        .unwrap();
+    let expr2 = Regexp::new(".+").unwrap();
+
+    assert!(!expr0.matches("a\nb"));
+    assert!(expr0.matches("a\rb"));
+    assert!(expr1.matches("a\nb"));
+
+    assert_eq!(
+        expr2.find_iter("ab\ncd").collect::<Vec<_>>(),
+        [(0, 2), (3, 5)]
+    );
+}
+
+#[test]
+fn multi_line() {
+    let multi_line = |pattern| {
+        RegexpBuilder::new()
+            .multi_line(true)
+            .build(pattern)
+            .unwrap()
+    };
+
+    let expr0 = multi_line("^bar");
+    let expr1 = multi_line("foo$");
+    let expr2 = multi_line("^\\w+$");
+
+    assert_eq!(expr0.find("foo\nbar"), Some((4, 7)));
+    assert_eq!(Regexp::new("^bar").unwrap().find("foo\nbar"), None);
+
+    assert_eq!(expr1.find("foo\nbar"), Some((0, 3)));
+    assert_eq!(Regexp::new("foo$").unwrap().find("foo\nbar"), None);
+
+    assert_eq!(
+        expr2.find_iter("foo\nbar\n\nbaz").collect::<Vec<_>>(),
+        [(0, 3), (4, 7), (9, 12)]
+    );
+    assert!(!expr2.matches("foo\nbar"));
+}
+
+#[test]
+fn ignore_whitespace() {
+    let verbose = |pattern| {
+        RegexpBuilder::new()
+            .ignore_whitespace(true)
+            .build(pattern)
+            .unwrap()
+    };
+
+    let expr0 = verbose("a b c");
+    let expr1 = verbose("a\\ b[ ]c");
+    let expr2 = verbose("(\\d+) # the year\n - (\\d+) # the month\n");
+
+    assert!(expr0.matches("abc"));
+    assert!(!expr0.matches("a b c"));
+
+    assert!(expr1.matches("a b c"));
+    assert!(!expr1.matches("abc"));
+
+    assert!(expr2.matches("2024-10"));
+    assert!(Regexp::new("a b").unwrap().matches("a b"));
+}
+
+#[test]
+fn lookahead() {
+    let expr0 = Regexp::new("foo(?=bar)").unwrap();
+    let expr1 = Regexp::new("\\w+(?=,)").unwrap();
+    let expr2 = Regexp::new("(?=(\\d+))\\w+").unwrap();
+
+    assert_eq!(expr0.find("foobar"), Some((0, 3)));
+    assert_eq!(expr0.find("foobaz"), None);
+    assert_eq!(expr0.find("foofoobar"), Some((3, 6)));
+    assert!(!expr0.matches("foobar"));
+
+    assert_eq!(expr1.find("one, two"), Some((0, 3)));
+
+    assert_eq!(
+        expr2.captures("123abc"),
+        Some(vec![Some("123abc".to_string()), Some("123".to_string())])
+    );
+}
+
+#[test]
+fn negative_lookahead() {
+    let expr0 = Regexp::new("q(?!u)").unwrap();
+    let expr1 = Regexp::new("(?!un)\\w+").unwrap();
+
+    assert_eq!(expr0.find("qat"), Some((0, 1)));
+    assert_eq!(expr0.find("quit"), None);
+    assert_eq!(expr0.find("quiq"), Some((3, 4)));
+
+    assert!(expr1.matches("done"));
+    assert!(!expr1.matches("undone"));
+}
+
+#[test]
+fn escape() {
+    assert_eq!(Regexp::escape("a.b"), "a\\.b");
+    assert_eq!(Regexp::escape("(1+1)*2"), "\\(1\\+1\\)\\*2");
+
+    for text in ["a.b", "[x]{2}|^$", "c:\\dir\\*.rs", "why?"] {
+        let expr = Regexp::new(&Regexp::escape(text)).unwrap();
+
+        assert!(expr.matches(text));
+        assert_eq!(
+            expr.find(&format!("__{}__", text)),
+            Some((2, text.chars().count() + 2))
+        );
+    }
+
+    let expr = Regexp::new(&Regexp::escape("a.b")).unwrap();
+
+    assert!(!expr.matches("axb"));
+    assert!(!expr.matches("a.bb"));
+}
+
+#[test]
+fn literal() {
+    let expr = Regexp::literal("a.*b");
+
+    assert!(expr.matches("a.*b"));
+    assert!(!expr.matches("axxb"));
+    assert_eq!(expr, Regexp::new(&Regexp::escape("a.*b")).unwrap());
+
+    assert!(Regexp::literal("").matches(""));
+    assert_eq!(Regexp::literal("(").find("a(b"), Some((1, 2)));
+}
+
+#[test]
+fn from_str() {
+    let expr: Regexp = "ab.c".parse().unwrap();
+
+    assert!(expr.matches("abxc"));
+    assert_eq!(expr, Regexp::new("ab.c").unwrap());
+
+    assert_eq!(
+        "a[b".parse::<Regexp>(),
+        Err(RegexpParsingError::UnterminatedClass { index: 1 })
+    );
+}
+
+#[test]
+fn try_from() {
+    fn compile(
+        pattern: impl TryInto<Regexp, Error = RegexpParsingError>,
+    ) -> Result<Regexp, RegexpParsingError> {
+        pattern.try_into()
+    }
+
+    assert!(compile("a+b").unwrap().matches("aab"));
+    assert!(compile(String::from("a+b")).unwrap().matches("aab"));
+    assert_eq!(
+        Regexp::try_from("x|y").unwrap(),
+        Regexp::new("x|y").unwrap()
+    );
+
+    assert_eq!(
+        compile("(a"),
+        Err(RegexpParsingError::UnclosedParen { index: 0 })
+    );
+    assert_eq!(
+        Regexp::try_from(String::from("a\\")),
+        Err(RegexpParsingError::TrailingBackslash { index: 1 })
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    for pattern in ["a.b", "(?<year>\\d{4})-(\\d\\d)", "x|[^\"]*", ""] {
+        let expr = Regexp::new(pattern).unwrap();
+        let json = serde_json::to_string(&expr).unwrap();
+
+        assert_eq!(json, serde_json::to_string(pattern).unwrap());
+        assert_eq!(serde_json::from_str::<Regexp>(&json).unwrap(), expr);
+    }
+
+    let error = serde_json::from_str::<Regexp>("\"a(b\"").unwrap_err();
+
+    assert!(error
+        .to_string()
+        .contains("unclosed parenthesis at index 1"));
+}
+
+#[test]
+fn as_str() {
+    assert_eq!(Regexp::new("a.b").unwrap().as_str(), "a.b");
+    assert_eq!(Regexp::new("(x|y)+").unwrap().as_str(), "(x|y)+");
+    assert_eq!(Regexp::new_case_insensitive("abc").unwrap().as_str(), "abc");
+    assert_eq!(Regexp::literal("1+1").as_str(), "1\\+1");
+}
+
+#[test]
+fn display() {
+    assert_eq!(Regexp::new("ab.?c*").unwrap().to_string(), "ab.?c*");
+    #[cfg(not(feature = "unicode"))]
+    assert_eq!(
+        Regexp::new("\\d+\\.x{2,}").unwrap().to_string(),
+        "[0-9]+\\.x{2,}"
+    );
+    assert_eq!(
+        Regexp::new("(?<y>a|b)(?:c)").unwrap().to_string(),
+        "(?<y>a|b)(?:c)"
+    );
+    assert_eq!(Regexp::literal("1+[x]").to_string(), "1\\+\\[x\\]");
+
+    for pattern in [
+        "ab.?c",
+        "^a+?b*?\\$$",
+        "x{3}y{1,2}z{0,}",
+        "(a|bc)+(?:d|)\\b\\B",
+        "[^a-z\\]\\-]\\s\\W",
+        "(?<word>\\w+)(?=!)(?!\\?)",
+        "\\n\\t\\{\\}|\\\\",
+        "",
+    ] {
+        let expr = Regexp::new(pattern).unwrap();
+        assert_eq!(Regexp::new(&expr.to_string()).unwrap(), expr, "{}", pattern);
+    }
+
+    let expr = RegexpBuilder::new()
+        .ignore_whitespace(true)
+        .build("a\\ b\\#")
+        .unwrap();
+    let reparsed = RegexpBuilder::new()
+        .ignore_whitespace(true)
+        .build(&expr.to_string())
+        .unwrap();
+
+    assert_eq!(reparsed, expr);
+}
+
+#[test]
+fn nfa() {
+    let expr = Regexp::new("a.*a.*a.*a.*b").unwrap();
+    let text = "a".repeat(1000);
+
+    // Matched by the NFA without backtracking, see the allocations bench
+    // for how long it takes.
+    assert!(expr.compile().is_some());
+    assert!(!expr.matches(&text));
+    assert!(expr.matches(&format!("{}b", text)));
+
+    for (pattern, text, expected) in [
+        ("(a|ab)(c|bcd)", "abcd", true),
+        ("(x*)*y", "xxy", true),
+        ("a{2,3}", "aaaa", false),
+        ("\\bfoo\\b.*", "foo bar", true),
+        ("(?=ab)a.", "ab", true),
+        ("(?!ab)a.", "ab", false),
+        ("^$", "", true),
+    ] {
+        assert_eq!(
+            Regexp::new(pattern).unwrap().matches(text),
+            expected,
+            "{}",
+            pattern
+        );
+    }
+}
+
+#[test]
+fn memoization() {
+    let text = "a".repeat(64);
+
+    assert_eq!(Regexp::new("(a*)*b").unwrap().find(&text), None);
+    assert_eq!(Regexp::new("(a|aa)+b").unwrap().captures(&text), None);
+    assert_eq!(Regexp::new("(a+)+$").unwrap().find(&text), Some((0, 64)));
+
+    // Trying every way to match, each repeat is only matched once at every
+    // index before its failure is remembered, so where it would take 2^64
+    // attempts without the memo it stays quadratic in the length.
+    let chars: Vec<char> = text.chars().collect();
+
+    for pattern in ["(a*)*b", "(a|aa)+b", "(a+)+$"] {
+        let expr = Regexp::new(pattern).unwrap();
+        let mut state = expr.new_state(&chars);
+
+        assert!(!expr.start_match(&mut state, 0, &mut |_, _| false));
+        assert!(
+            state.repeats + state.failed.len() <= chars.len().pow(2),
+            "{pattern}"
+        );
+    }
+
+    let captures = Regexp::new("(a*)*(b)").unwrap().captures("aab").unwrap();
+    assert_eq!(captures[2].as_deref(), Some("b"));
+}
+
+#[test]
+fn bytes() {
+    let expr = Regexp::new("a.c").unwrap();
+
+    assert!(expr.matches_bytes(b"a\xffc"));
+    assert!(!expr.matches_bytes(b"a\xff\xfec"));
+    assert_eq!(expr.find_bytes(b"\x80\x81abc\xc3"), Some((2, 5)));
+    assert_eq!(expr.find_bytes(b"\xc3\x28"), None);
+
+    let expr = Regexp::new("[\0-\x7f]*").unwrap();
+
+    assert!(expr.matches_bytes(b"ab\x7f"));
+    assert!(!expr.matches_bytes(b"ab\xe9"));
+    assert_eq!(
+        Regexp::new("\\d+").unwrap().find_bytes(b"\xfe\xfe42\xfe"),
+        Some((2, 4))
+    );
+
+    assert!(Regexp::new("caf\u{e9}").unwrap().matches_bytes(b"caf\xe9"));
+    assert!(!Regexp::new("caf\u{e9}")
+        .unwrap()
+        .matches_bytes("café".as_bytes()));
+}
+
+#[test]
+fn byte_ranges() {
+    let text = "héllo wörld";
+    let expr = Regexp::new("w.r").unwrap();
+    let range = expr.find_range(text).unwrap();
+
+    assert_eq!(expr.find(text), Some((6, 9)));
+    assert_eq!(range, 7..11);
+    assert_eq!(&text[range], "wör");
+
+    let captures = Regexp::new("(h.)(x)?(l+)")
+        .unwrap()
+        .capture_ranges(text)
+        .unwrap();
+
+    assert_eq!(captures, [Some(0..5), Some(0..3), None, Some(3..5)]);
+    assert_eq!(&text[captures[1].clone().unwrap()], "hé");
+    assert_eq!(Regexp::new("$").unwrap().find_range(text), Some(13..13));
+    assert_eq!(Regexp::new("z").unwrap().find_range(text), None);
+}
+
+#[test]
+fn shortest_match() {
+    assert_eq!(Regexp::new("a+").unwrap().shortest_match("aaa"), Some(1));
+    assert_eq!(Regexp::new("a*").unwrap().shortest_match("aaa"), Some(0));
+    assert_eq!(Regexp::new("ab|a").unwrap().shortest_match("abc"), Some(1));
+    #[cfg(not(feature = "unicode"))]
+    assert_eq!(
+        Regexp::new("\\w+\\b").unwrap().shortest_match("héllo!"),
+        Some(1)
+    );
+    #[cfg(feature = "unicode")]
+    assert_eq!(
+        Regexp::new("\\w+\\b").unwrap().shortest_match("héllo!"),
+        Some(5)
+    );
+    assert_eq!(
+        Regexp::new("[a-z]+\\b").unwrap().shortest_match("hello!"),
+        Some(5)
+    );
+    assert_eq!(Regexp::new("a+").unwrap().shortest_match("baa"), None);
+    assert_eq!(Regexp::new("x").unwrap().shortest_match(""), None);
+}
+
+#[test]
+fn is_match() {
+    let expr = Regexp::new("abc").unwrap();
+
+    assert!(expr.matches("abc"));
+    assert!(!expr.matches("xabcx"));
+    assert!(expr.is_match("abc"));
+    assert!(expr.is_match("xabcx"));
+    assert!(!expr.is_match("xabx"));
+
+    assert!(Regexp::new("^ab").unwrap().is_match("abx"));
+    assert!(!Regexp::new("^ab").unwrap().is_match("xab"));
+    assert!(Regexp::new("x*").unwrap().is_match(""));
+}
+
+#[test]
+fn combinators() {
+    let expr = Regexp::literal("a").star().then(Regexp::literal("b"));
+
+    assert!(expr.matches("aaab"));
+    assert!(expr.matches("b"));
+    assert!(!expr.matches("aaa"));
+    assert_eq!(expr.as_str(), "(?:a)*b");
+
+    let expr = Regexp::literal("x")
+        .or(Regexp::literal("yz"))
+        .then(Regexp::literal("!"));
+
+    assert!(expr.matches("x!"));
+    assert!(expr.matches("yz!"));
+    assert!(!expr.matches("x"));
+    assert_eq!(
+        Regexp::new(expr.as_str()).unwrap().find("ayz!"),
+        Some((1, 4))
+    );
+
+    let expr = Regexp::literal("-")
+        .optional()
+        .then(Regexp::new("\\d+").unwrap());
+
+    assert!(expr.matches("-12"));
+    assert!(expr.matches("12"));
+    assert!(!expr.matches("--12"));
+
+    let captures = Regexp::new("(a)")
+        .unwrap()
+        .then(Regexp::new("(b)|(c)").unwrap())
+        .captures("ac")
+        .unwrap();
+
+    assert_eq!(
+        captures,
+        [Some("ac".into()), Some("a".into()), None, Some("c".into())]
+    );
+
+    let expr = Regexp::new_case_insensitive("a")
+        .unwrap()
+        .then(Regexp::literal("b"));
+
+    assert!(expr.matches("Ab"));
+    assert!(!expr.matches("AB"));
+}
+
+#[test]
+fn repeat() {
+    let expr = Regexp::literal("ab").repeat(3);
+
+    assert!(expr.matches("ababab"));
+    assert!(!expr.matches("abab"));
+    assert!(!expr.matches("abababab"));
+    assert_eq!(expr.as_str(), "(?:ab){3}");
+
+    let expr = Regexp::new("a|b").unwrap().repeat(2);
+
+    assert!(expr.matches("ba"));
+    assert!(!expr.matches("b"));
+
+    let expr = Regexp::literal("x").repeat(0);
+
+    assert!(expr.matches(""));
+    assert!(!expr.matches("x"));
+    assert_eq!(expr.find("yx"), Some((0, 0)));
+}
+
+#[test]
+fn empty_groups() {
+    let expr = Regexp::new("()").unwrap();
+
+    assert!(expr.matches(""));
+    assert!(!expr.matches("a"));
+    assert_eq!(
+        expr.captures("x"),
+        Some(vec![Some("".into()), Some("".into())])
+    );
+
+    assert!(Regexp::new("a()b").unwrap().matches("ab"));
+    assert!(Regexp::new("a(?:)b").unwrap().matches("ab"));
+    assert!(Regexp::new("a()*b").unwrap().matches("ab"));
+    assert!(Regexp::new("a()+b").unwrap().matches("ab"));
+    assert_eq!(Regexp::new("()*").unwrap().find("aa"), Some((0, 0)));
+    assert_eq!(Regexp::new("a()*").unwrap().find_iter("aa").count(), 2);
+
+    let expr = Regexp::new("(|a)b").unwrap();
+
+    assert!(expr.matches("b"));
+    assert!(expr.matches("ab"));
+    assert_eq!(expr.captures("ab").unwrap()[1].as_deref(), Some("a"));
+    assert_eq!(expr.captures("b").unwrap()[1].as_deref(), Some(""));
+}
+
+#[test]
+fn dangling_quantifiers() {
+    assert_eq!(
+        Regexp::new("*ab"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 0 })
+    );
+    assert_eq!(
+        Regexp::new("?x"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 0 })
+    );
+    assert_eq!(
+        Regexp::new("a|*b"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 2 })
+    );
+    assert_eq!(
+        Regexp::new("a(*b)"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 2 })
+    );
+    assert_eq!(
+        Regexp::new("+").unwrap_err().to_string(),
+        "quantifier without anything to repeat at index 0"
+    );
+
+    assert!(Regexp::new("\\*ab").unwrap().matches("*ab"));
+    assert!(Regexp::new("[*]ab").unwrap().matches("*ab"));
+}
+
+#[test]
+fn redundant_quantifiers() {
+    assert_eq!(
+        Regexp::new("a**"),
+        Err(RegexpParsingError::RedundantQuantifier { index: 2 })
+    );
+    assert_eq!(
+        Regexp::new("a???"),
+        Err(RegexpParsingError::RedundantQuantifier { index: 3 })
+    );
+    assert_eq!(
+        Regexp::new("xa*?+"),
+        Err(RegexpParsingError::RedundantQuantifier { index: 4 })
+    );
+    assert_eq!(
+        Regexp::new("(a{2}{3})"),
+        Err(RegexpParsingError::RedundantQuantifier { index: 5 })
+    );
+
+    let expr = Regexp::new("a+?").unwrap();
+
+    assert_eq!(expr.find("aaa"), Some((0, 1)));
+    assert!(Regexp::new("a*{x}").unwrap().matches("aa{x}"));
+    assert!(Regexp::new("a*\\*").unwrap().matches("aa*"));
+}
+
+#[test]
+fn possessive_quantifiers() {
+    assert!(Regexp::new("a*a").unwrap().matches("aaa"));
+    assert!(!Regexp::new("a*+a").unwrap().matches("aaa"));
+    assert!(!Regexp::new("a++a").unwrap().matches("aaa"));
+    assert!(!Regexp::new("a?+a").unwrap().matches("a"));
+    assert!(Regexp::new("a?+a").unwrap().matches("aa"));
+
+    let expr = Regexp::new("\"[^\"]*+\"").unwrap();
+
+    assert_eq!(expr.find("say \"hi\" now"), Some((4, 8)));
+    assert_eq!(expr.find("\"unterminated"), None);
+
+    let captures = Regexp::new("(a|ab)++c").unwrap().captures("xaabc");
+    assert_eq!(captures, None);
+    assert_eq!(
+        Regexp::new("(ab|a)++c").unwrap().find("xaabc"),
+        Some((1, 5))
+    );
+    assert_eq!(Regexp::new("a*+b").unwrap().shortest_match("aab"), Some(3));
+    assert_eq!(Regexp::new("x++y").unwrap().to_string(), "x++y");
+}
+
+#[test]
+fn atomic_groups() {
+    assert!(Regexp::new("(a|ab)c").unwrap().matches("abc"));
+    assert!(!Regexp::new("(?>a|ab)c").unwrap().matches("abc"));
+    assert_eq!(Regexp::new("(?>a|ab)c").unwrap().find("abc"), None);
+    assert_eq!(Regexp::new("(?>ab|a)c").unwrap().find("abc"), Some((0, 3)));
+    assert!(!Regexp::new("(?>a*)a").unwrap().matches("aaa"));
+
+    let captures = Regexp::new("(?>(a+))(b)").unwrap().captures("aab").unwrap();
+    assert_eq!(captures[1].as_deref(), Some("aa"));
+    assert_eq!(captures[2].as_deref(), Some("b"));
+    assert_eq!(Regexp::new("(?>x|y)+").unwrap().to_string(), "(?>x|y)+");
+}
+
+#[test]
+fn hex_escapes() {
+    assert!(Regexp::new("\\x41").unwrap().matches("A"));
+    assert!(Regexp::new("\\x{263A}").unwrap().matches("☺"));
+    assert!(Regexp::new("\\x{1F600}+").unwrap().matches("😀😀"));
+    assert!(Regexp::new("a\\x2e\\x2Eb").unwrap().matches("a..b"));
+    assert!(Regexp::new("[\\x41-\\x{5A}]+").unwrap().matches("XYZ"));
+    assert!(!Regexp::new("[\\x41-\\x{5A}]+").unwrap().matches("xyz"));
+
+    for (pattern, index) in [
+        ("\\x4", 0),
+        ("a\\xg1", 1),
+        ("\\x{}", 0),
+        ("\\x{D800}", 0),
+        ("\\x{110000}", 0),
+        ("\\x{1234567}", 0),
+        ("\\x{41", 0),
+        ("[a\\x{zz}]", 2),
+    ] {
+        assert_eq!(
+            Regexp::new(pattern),
+            Err(RegexpParsingError::InvalidEscape { index }),
+            "{}",
+            pattern
+        );
+    }
+
+    let expr = Regexp::new("\\x00[\\x01-\\x1f]").unwrap();
+    assert_eq!(expr.to_string(), "\\x{0}[\\x{1}-\\x{1F}]");
+    assert_eq!(Regexp::new(&expr.to_string()).unwrap(), expr);
+}
+
+#[test]
+fn unicode_escapes() {
+    assert!(Regexp::new("\\u{41}").unwrap().matches("A"));
+    assert!(Regexp::new("\\u{263a}\\u{1F600}").unwrap().matches("☺😀"));
+
+    let greek = Regexp::new("[\\u{3b1}-\\u{3c9}]+").unwrap();
+
+    assert!(greek.matches("λογος"));
+    assert!(!greek.matches("λόγος"));
+
+    for pattern in [
+        "\\u{DFFF}",
+        "\\u{110000}",
+        "\\u{0000041}",
+        "\\u41",
+        "\\u{}",
+        "\\u{4x}",
+    ] {
+        assert_eq!(
+            Regexp::new(pattern),
+            Err(RegexpParsingError::InvalidEscape { index: 0 }),
+            "{}",
+            pattern
+        );
+    }
+}
+
+#[test]
+fn count() {
+    assert_eq!(Regexp::new("\\d+").unwrap().count("a1b22c333"), 3);
+    assert_eq!(Regexp::new("a*").unwrap().count("aa"), 2);
+    assert_eq!(Regexp::new("a*").unwrap().count("bab"), 4);
+    assert_eq!(Regexp::new("x").unwrap().count(""), 0);
+    assert_eq!(Regexp::new("").unwrap().count("abc"), 4);
+}
+
+#[test]
+fn matches_at() {
+    let expr = Regexp::new("\\d+").unwrap();
+
+    assert_eq!(expr.matches_at("a123", 1), Some(3));
+    assert_eq!(expr.matches_at("a123", 2), Some(2));
+    assert_eq!(expr.matches_at("a123", 0), None);
+    assert_eq!(expr.matches_at("a123", 4), None);
+    assert_eq!(expr.matches_at("a123", 5), None);
+
+    assert_eq!(Regexp::new("^b").unwrap().matches_at("ab", 1), None);
+    assert_eq!(Regexp::new("\\bb").unwrap().matches_at("ab", 1), None);
+    assert_eq!(Regexp::new("x*").unwrap().matches_at("ab", 2), Some(0));
+    assert_eq!(Regexp::new("é+").unwrap().matches_at("éééa", 1), Some(2));
+}
+
+#[test]
+fn posix_classes() {
+    let expr = Regexp::new("[[:digit:]x]+").unwrap();
+
+    assert!(expr.matches("1x2"));
+    assert!(!expr.matches("1y2"));
+
+    assert!(Regexp::new("[[:alpha:]]+").unwrap().matches("abcXYZ"));
+    assert!(Regexp::new("[[:space:]]").unwrap().matches("\t"));
+    assert!(Regexp::new("[[:alnum:]_]+").unwrap().matches("a_1"));
+    assert!(Regexp::new("[[:upper:]][[:lower:]]+")
+        .unwrap()
+        .matches("Hello"));
+    assert!(!Regexp::new("[[:upper:]][[:lower:]]+")
+        .unwrap()
+        .matches("hello"));
+    assert!(Regexp::new("[^[:punct:][:space:]]+")
+        .unwrap()
+        .matches("abc"));
+    assert!(!Regexp::new("[^[:punct:][:space:]]+")
+        .unwrap()
+        .matches("a,c"));
+    assert!(Regexp::new("[[:]+").unwrap().matches("[::["));
+
+    assert_eq!(
+        Regexp::new("a[[:foo:]]"),
+        Err(RegexpParsingError::UnknownPosixClass { index: 2 })
+    );
+}
+
+#[test]
+fn backrefs() {
+    let expr = Regexp::new("(ab)\\1").unwrap();
+
+    assert!(expr.matches("abab"));
+    assert!(!expr.matches("abcd"));
+    assert!(!expr.matches("ab"));
+
+    assert!(Regexp::new("(a|b)\\1").unwrap().matches("bb"));
+    assert!(!Regexp::new("(a|b)\\1").unwrap().matches("ab"));
+    assert_eq!(
+        Regexp::new("(\\w+) \\1").unwrap().find("say hey hey"),
+        Some((4, 11))
+    );
+    assert_eq!(Regexp::new("(a)(b)?\\2").unwrap().find("ab a"), None);
+    assert!(Regexp::new("(a)\\10").unwrap().matches("aa0"));
+    assert!(Regexp::new_case_insensitive("(a)\\1")
+        .unwrap()
+        .matches("aA"));
+    assert!(Regexp::new("(a*)*b\\1").unwrap().matches("aaba"));
+
+    assert_eq!(
+        Regexp::new("(a)\\2"),
+        Err(RegexpParsingError::InvalidBackref { index: 3 })
+    );
+    assert_eq!(
+        Regexp::new("\\1(a)"),
+        Err(RegexpParsingError::InvalidBackref { index: 0 })
+    );
+    assert_eq!(Regexp::new("(a)\\1").unwrap().to_string(), "(a)\\1");
+}
+
+#[test]
+fn text_anchors() {
+    let multi_line = |pattern| {
+        RegexpBuilder::new()
+            .multi_line(true)
+            .build(pattern)
+            .unwrap()
+    };
+
+    assert_eq!(multi_line("^b").find("a\nb"), Some((2, 3)));
+    assert_eq!(multi_line("\\Ab").find("a\nb"), None);
+    assert_eq!(multi_line("\\Aa").find("a\nb"), Some((0, 1)));
+    assert_eq!(multi_line("a$").find("a\nb"), Some((0, 1)));
+    assert_eq!(multi_line("a\\z").find("a\nb"), None);
+    assert_eq!(multi_line("b\\z").find("a\nb"), Some((2, 3)));
+    assert_eq!(multi_line("\\A").find_iter("a\nb").count(), 1);
+
+    assert!(Regexp::new("\\Aab\\z").unwrap().matches("ab"));
+    assert_eq!(Regexp::new("\\Aa|b\\z").unwrap().to_string(), "\\Aa|b\\z");
+}
+
+#[test]
+fn debug_tree() {
+    let tree = Regexp::new("a(bc)*d").unwrap().debug_tree();
+
+    assert_eq!(
+        tree,
+        "Char('a')\nGroup(1) *\n  Literal(\"bc\")\nChar('d')\n"
+    );
+
+    let tree = Regexp::new("(?<x>[0-9]|y+?)\\b").unwrap().debug_tree();
+
+    assert_eq!(
+        tree,
+        concat!(
+            "Group(1, \"x\")\n",
+            "  Alternation\n",
+            "    Branch\n",
+            "      Class([0-9])\n",
+            "    Branch\n",
+            "      Char('y') +?\n",
+            "WordBoundary\n",
+        )
+    );
+}
+
+#[test]
+fn literal_runs() {
+    let expr = Regexp::new("abc*").unwrap();
+
+    assert_eq!(expr.debug_tree(), "Literal(\"ab\")\nChar('c') *\n");
+    assert!(expr.matches("ab"));
+    assert!(expr.matches("abccc"));
+    assert!(!expr.matches("abab"));
+
+    let expr = Regexp::new("x(yz)+|hello").unwrap();
+
+    assert_eq!(expr.find("say hello"), Some((4, 9)));
+    assert_eq!(expr.find("xyzyz"), Some((0, 5)));
+    assert!(Regexp::new_case_insensitive("héllo")
+        .unwrap()
+        .matches("HÉLLO"));
+    assert_eq!(Regexp::new("ab").unwrap(), Regexp::literal("ab"));
+    assert_eq!(
+        Regexp::literal("a").then(Regexp::literal("b")),
+        Regexp::literal("ab")
+    );
+
+    let needle = "abcdefghij".repeat(1000);
+    let haystack = format!("{}{}", "abcdefghi".repeat(1000), needle);
+    let expr = Regexp::literal(&needle);
+
+    // One literal token, so offsets where it doesn't start are skipped.
+    assert_eq!(expr.tokens.len(), 1);
+    assert_eq!(expr.literal_prefix(), (needle.chars().collect(), true));
+    assert_eq!(expr.find(&haystack), Some((9000, 19000)));
+    assert!(expr.matches(&needle));
+}
+
+#[test]
+fn starts_and_ends_with() {
+    assert!(Regexp::new("ab").unwrap().starts_with("abcd"));
+    assert!(!Regexp::new("ab").unwrap().starts_with("xabcd"));
+    assert!(Regexp::new("cd").unwrap().ends_with("abcd"));
+    assert!(!Regexp::new("cd").unwrap().ends_with("abcdx"));
+
+    assert!(Regexp::new("a+?").unwrap().ends_with("baaa"));
+    assert!(Regexp::new("\\d*").unwrap().starts_with("x"));
+    assert!(Regexp::new("x*").unwrap().ends_with(""));
+    assert!(!Regexp::new("b").unwrap().ends_with(""));
+}
+
+#[test]
+fn replacen() {
+    let expr = Regexp::new("\\d+").unwrap();
+
+    assert_eq!(expr.replacen("a1b2c3", 2, "#"), "a#b#c3");
+    assert_eq!(expr.replacen("a1b2c3", 1, "#"), "a#b2c3");
+    assert_eq!(expr.replacen("a1b2c3", 5, "#"), "a#b#c#");
+    assert_eq!(expr.replacen("a1b2c3", 0, "#"), "a#b#c#");
+    assert_eq!(expr.replacen("abc", 1, "#"), "abc");
+}
+
+#[test]
+fn replacement_templates() {
+    let expr = Regexp::new("(\\w+)=(\\w+)").unwrap();
+
+    assert_eq!(expr.replace_all("a=b", "$2=$1"), "b=a");
+    assert_eq!(expr.replace_all("a=b c=d", "$2=$1"), "b=a d=c");
+    assert_eq!(expr.replace_all("a=b", "[$0]"), "[a=b]");
+    assert_eq!(expr.replace_all("a=b", "${2}1"), "b1");
+    assert_eq!(expr.replace_all("a=b", "$$1 costs $9"), "$1 costs ");
+    assert_eq!(expr.replace_all("a=b", "$ and ${"), "$ and ${");
+    assert_eq!(expr.replace("a=b c=d", "$2$1"), "ba c=d");
+    assert_eq!(expr.replacen("a=b c=d e=f", 2, "$2"), "b d e=f");
+
+    let expr = Regexp::new("(?<key>\\w+): (?<value>\\d+)?").unwrap();
+
+    assert_eq!(expr.replace_all("x: 1, y: ", "${value}${key}"), "1x, y");
+    assert_eq!(expr.replace_all("x: 1", "${missing}!"), "!");
+    assert_eq!(
+        Regexp::new("é").unwrap().replace_all("héllo", "<$0>"),
+        "h<é>llo"
+    );
+}
+
+#[test]
+fn captures_iter() {
+    let expr = Regexp::new("(\\w)=(\\d)").unwrap();
+    let captures: Vec<_> = expr.captures_iter("a=1 b=2").collect();
+    let group = |text: &str| Some(text.to_string());
+
+    assert_eq!(
+        captures,
+        [
+            vec![group("a=1"), group("a"), group("1")],
+            vec![group("b=2"), group("b"), group("2")],
+        ]
+    );
+
+    let captures: Vec<_> = Regexp::new("(x)?y").unwrap().captures_iter("yxy").collect();
+
+    assert_eq!(
+        captures,
+        [vec![group("y"), None], vec![group("xy"), group("x")]]
+    );
+    assert_eq!(expr.captures_iter("none").count(), 0);
+}
+
+#[test]
+fn captures_len() {
+    assert_eq!(Regexp::new("a(b(c))d").unwrap().captures_len(), 3);
+    assert_eq!(Regexp::new("abc").unwrap().captures_len(), 1);
+    assert_eq!(Regexp::new("(?:a)(?=b)(c|(d))").unwrap().captures_len(), 3);
+    assert_eq!(Regexp::new("(?<x>a)|(b)").unwrap().captures_len(), 3);
+
+    let expr = Regexp::new("(a)(b)?").unwrap();
+    assert_eq!(expr.captures("a").unwrap().len(), expr.captures_len());
+}
+
+#[test]
+fn inline_flags() {
+    assert!(Regexp::new("(?i)hello").unwrap().matches("HELLO"));
+    assert!(Regexp::new("(?i:ab)CD").unwrap().matches("ABCD"));
+    assert!(!Regexp::new("(?i:ab)CD").unwrap().matches("abcd"));
+    assert!(Regexp::new("ab(?i)cd").unwrap().matches("abCD"));
+    assert!(!Regexp::new("ab(?i)cd").unwrap().matches("ABcd"));
+    assert!(Regexp::new("a(?i)b|c").unwrap().matches("C"));
+    assert!(!Regexp::new("a(?i)b|c").unwrap().matches("AB"));
+    assert!(Regexp::new("(a(?i)b)c").unwrap().matches("aBc"));
+    assert!(!Regexp::new("(a(?i)b)c").unwrap().matches("aBC"));
+    assert!(Regexp::new_case_insensitive("a(?-i)b")
+        .unwrap()
+        .matches("Ab"));
+    assert!(!Regexp::new_case_insensitive("a(?-i)b")
+        .unwrap()
+        .matches("AB"));
+
+    let expr = Regexp::new("(?im)^x$").unwrap();
+
+    assert_eq!(expr.find("a\nX\nb"), Some((2, 3)));
+    assert!(Regexp::new("(?s).").unwrap().matches("\n"));
+    assert!(Regexp::new("(?x) a b # comment").unwrap().matches("ab"));
+
+    let captures = Regexp::new("(a)(?i)(b)").unwrap().captures("aB").unwrap();
+    assert_eq!(captures[2].as_deref(), Some("B"));
+
+    assert_eq!(
+        Regexp::new("a(?iq)"),
+        Err(RegexpParsingError::UnknownFlag { index: 4 })
+    );
+    assert_eq!(
+        Regexp::new("(?i)*"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 4 })
+    );
+
+    for pattern in [
+        "(?i:ab)CD",
+        "ab(?is)c.",
+        "(?m)a(?-m:^)",
+        "a|(?i)b|c",
+        "(a(?x)b)",
+    ] {
+        let expr = Regexp::new(pattern).unwrap();
+        assert_eq!(Regexp::new(&expr.to_string()).unwrap(), expr, "{}", pattern);
+    }
+}
+
+#[test]
+fn quoting() {
+    let expr = Regexp::new("\\Qa.*b\\E").unwrap();
+
+    assert!(expr.matches("a.*b"));
+    assert!(!expr.matches("axxb"));
+    assert_eq!(expr, Regexp::literal("a.*b"));
+
+    assert!(Regexp::new("x\\Q(|)\\E+").unwrap().matches("x(|))"));
+    assert!(Regexp::new("(\\Q)\\E)").unwrap().matches(")"));
+    assert!(Regexp::new("\\Q[\\d]").unwrap().matches("[\\d]"));
+    assert!(Regexp::new("a\\Q\\E+").unwrap().matches("aa"));
+    assert!(Regexp::new("\\Q").unwrap().matches(""));
+}
+
+#[test]
+fn comment_groups() {
+    let expr = Regexp::new("a(?#note)b").unwrap();
+
+    assert!(expr.matches("ab"));
+    assert_eq!(expr, Regexp::literal("ab"));
+
+    let expr = Regexp::new("(?#a (comment)(a)(?#\\)(b)").unwrap();
+    assert_eq!(expr.captures_len(), 3);
+    assert_eq!(
+        expr.captures("ab").unwrap(),
+        vec![
+            Some("ab".to_string()),
+            Some("a".to_string()),
+            Some("b".to_string())
+        ]
+    );
+
+    assert!(Regexp::new("(x(?#))").unwrap().matches("x"));
+    assert_eq!(
+        Regexp::new("a(?#note"),
+        Err(RegexpParsingError::UnterminatedComment { index: 1 })
+    );
+}
+
+#[test]
+fn find_anchored() {
+    let expr = Regexp::new("b").unwrap();
+
+    assert_eq!(expr.find("ab"), Some((1, 2)));
+    assert_eq!(expr.find_anchored("ab"), None);
+    assert_eq!(expr.find_anchored("ba"), Some((0, 1)));
+
+    assert_eq!(
+        Regexp::new("a*").unwrap().find_anchored("baa"),
+        Some((0, 0))
+    );
+    assert_eq!(
+        Regexp::new("\\d+").unwrap().find_anchored("12a3"),
+        Some((0, 2))
+    );
+}
+
+#[test]
+fn lazy_optional() {
+    let greedy = Regexp::new("a?a").unwrap();
+    let lazy = Regexp::new("a??a").unwrap();
+
+    assert_eq!(greedy.find("aa"), Some((0, 2)));
+    assert_eq!(lazy.find("aa"), Some((0, 1)));
+    assert!(lazy.matches("aa"));
+    assert!(lazy.matches("a"));
+
+    assert_eq!(
+        Regexp::new("(a??)(a*)").unwrap().captures("aa").unwrap(),
+        vec![
+            Some("aa".to_string()),
+            Some(String::new()),
+            Some("aa".to_string())
+        ]
+    );
+    assert_eq!(lazy.to_string(), "a??a");
+}
+
+#[test]
+fn optional_regressions() {
+    let expr = Regexp::new("a?b").unwrap();
+
+    assert!(expr.matches("b"));
+    assert!(expr.matches("ab"));
+    assert!(!expr.matches("aab"));
+    assert!(!expr.matches("a"));
+
+    assert_eq!(expr.find("b"), Some((0, 1)));
+    assert_eq!(expr.find("ab"), Some((0, 2)));
+    assert_eq!(expr.find("aab"), Some((1, 3)));
+    assert_eq!(expr.matches_at("aab", 0), None);
+    assert_eq!(
+        expr.find_iter("aabbab").collect::<Vec<_>>(),
+        [(1, 3), (3, 4), (4, 6)]
+    );
+}
+
+#[test]
+fn matches_chars() {
+    let exprs = ["a+b", "(a|bc)*", "\\w+(?=!)!", "(a)\\1", "x*+y", ""];
+    let inputs = ["", "ab", "aab", "abcbc", "foo!", "aa", "xxy", "ñ"];
+
+    for pattern in exprs {
+        let expr = Regexp::new(pattern).unwrap();
+
+        for input in inputs {
+            let chars: Vec<char> = input.chars().collect();
+            assert_eq!(expr.matches_chars(&chars), expr.matches(input));
+        }
+    }
+
+    assert!(Regexp::new("a.c").unwrap().matches_chars(&['a', 'b', 'c']));
+}
+
+#[test]
+fn quantified_groups() {
+    let star = Regexp::new("(ab)*").unwrap();
+    let plus = Regexp::new("(ab)+").unwrap();
+    let optional = Regexp::new("(ab)?").unwrap();
+    let alternation = Regexp::new("(a|b)*").unwrap();
+
+    assert!(star.matches(""));
+    assert!(star.matches("ababab"));
+    assert!(!star.matches("aba"));
+
+    assert!(!plus.matches(""));
+    assert!(plus.matches("ab"));
+    assert!(plus.matches("abab"));
+    assert!(!plus.matches("abb"));
+
+    assert!(optional.matches(""));
+    assert!(optional.matches("ab"));
+    assert!(!optional.matches("abab"));
+
+    assert!(alternation.matches(""));
+    assert!(alternation.matches("abbab"));
+    assert!(!alternation.matches("abc"));
+
+    assert_eq!(plus.find("xababy"), Some((1, 5)));
+    assert_eq!(
+        plus.captures("abab").unwrap(),
+        vec![Some("abab".to_string()), Some("ab".to_string())]
+    );
+    assert_eq!(
+        alternation.captures("ab").unwrap(),
+        vec![Some("ab".to_string()), Some("b".to_string())]
+    );
+    assert_eq!(star.captures("").unwrap(), vec![Some(String::new()), None]);
+    assert!(Regexp::new("((ab)+c)*").unwrap().matches("abcababc"));
+}
+
+#[test]
+fn find_longest() {
+    let expr = Regexp::new("a|ab").unwrap();
+
+    assert_eq!(expr.find("abc"), Some((0, 1)));
+    assert_eq!(expr.find_longest("abc"), Some((0, 2)));
+    assert_eq!(expr.find_longest("xab"), Some((1, 3)));
+    assert_eq!(expr.find_longest("b"), None);
+
+    assert_eq!(
+        Regexp::new("a+?").unwrap().find_longest("baaa"),
+        Some((1, 4))
+    );
+    assert_eq!(
+        Regexp::new("(a|ab)(c|bcd)").unwrap().find_longest("abcd"),
+        Some((0, 4))
+    );
+    assert_eq!(Regexp::new("x*").unwrap().find_longest("abc"), Some((0, 0)));
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn unicode() {
+    assert!(Regexp::new("\\w+").unwrap().matches("café"));
+    assert!(Regexp::new("\\w+").unwrap().matches("日本語_1"));
+    assert!(!Regexp::new("\\W").unwrap().matches("é"));
+    assert!(Regexp::new("[\\w-]+").unwrap().matches("naïve-été"));
+    assert!(Regexp::new("\\bcafé\\b").unwrap().is_match("un café."));
+
+    assert!(Regexp::new("\\d+").unwrap().matches("٣4६"));
+    assert!(!Regexp::new("\\d").unwrap().matches("½"));
+    assert!(!Regexp::new("\\d").unwrap().matches("a"));
+
+    assert!(Regexp::new_case_insensitive("É").unwrap().matches("é"));
+    assert!(Regexp::new_case_insensitive("s").unwrap().matches("ſ"));
+    assert!(Regexp::new_case_insensitive("[a-z]").unwrap().matches("ſ"));
+}
+
+#[test]
+fn split_terminator() {
+    let expr = Regexp::new(",").unwrap();
+
+    assert_eq!(expr.split("a,b,"), ["a", "b", ""]);
+    assert_eq!(expr.split_terminator("a,b,"), ["a", "b"]);
+    assert_eq!(expr.split_terminator("a,b"), ["a", "b"]);
+    assert_eq!(expr.split_terminator(",a,,"), ["", "a", ""]);
+    assert_eq!(expr.split_terminator(","), [""]);
+    assert_eq!(expr.split_terminator(""), [""]);
+    assert_eq!(
+        Regexp::new("x*").unwrap().split_terminator("ab"),
+        ["", "a", "b"]
+    );
+}
+
+#[test]
+fn find_overlapping() {
+    let expr = Regexp::new("aa").unwrap();
+
+    assert_eq!(expr.find_iter("aaaa").collect::<Vec<_>>(), [(0, 2), (2, 4)]);
+    assert_eq!(
+        expr.find_overlapping("aaaa").collect::<Vec<_>>(),
+        [(0, 2), (1, 3), (2, 4)]
+    );
+    assert_eq!(
+        Regexp::new("a+")
+            .unwrap()
+            .find_overlapping("baab")
+            .collect::<Vec<_>>(),
+        [(1, 3), (2, 3)]
+    );
+    assert_eq!(
+        Regexp::new("x?")
+            .unwrap()
+            .find_overlapping("x")
+            .collect::<Vec<_>>(),
+        [(0, 1), (1, 1)]
+    );
+    assert_eq!(expr.find_overlapping("ab").count(), 0);
+}
+
+#[test]
+fn len_bounds() {
+    let bounds = |pattern| {
+        let expr = Regexp::new(pattern).unwrap();
+        (expr.min_len(), expr.max_len())
+    };
+
+    assert_eq!(bounds("a{2,4}"), (2, Some(4)));
+    assert_eq!(bounds("a*"), (0, None));
+    assert_eq!(bounds("a+"), (1, None));
+    assert_eq!(bounds("ab?c"), (2, Some(3)));
+    assert_eq!(bounds("(ab|c)[xy]{3}"), (4, Some(5)));
+    assert_eq!(bounds("(a|bc*)."), (2, None));
+    assert_eq!(bounds("^\\b(?=abc)$"), (0, Some(0)));
+    assert_eq!(bounds("(?:){5,}"), (0, Some(0)));
+    assert_eq!(bounds("x{3,}"), (3, None));
+    assert_eq!(bounds("(a)\\1"), (1, None));
+    assert_eq!(bounds(""), (0, Some(0)));
+}
+
+#[test]
+fn unopened_parens() {
+    assert_eq!(
+        Regexp::new("ab)c"),
+        Err(RegexpParsingError::UnopenedParen { index: 2 })
+    );
+    assert_eq!(
+        Regexp::new("(a))"),
+        Err(RegexpParsingError::UnopenedParen { index: 3 })
+    );
+    assert_eq!(
+        Regexp::new("x|(a|b))"),
+        Err(RegexpParsingError::UnopenedParen { index: 7 })
+    );
+
+    assert!(Regexp::new("ab\\)c").unwrap().matches("ab)c"));
+    assert!(Regexp::new("[)]\\Q)\\E").unwrap().matches("))"));
+}
+
+#[test]
+fn balanced_parens() {
+    let expr = Regexp::new("(a)(b)").unwrap();
+
+    assert_eq!(expr.captures_len(), 3);
+    assert_eq!(
+        expr.captures("ab").unwrap(),
+        vec![
+            Some("ab".to_string()),
+            Some("a".to_string()),
+            Some("b".to_string())
+        ]
+    );
+    assert!(!expr.matches("a)(b"));
+
+    let expr = Regexp::new("((a)(b(c)))").unwrap();
+
+    assert_eq!(
+        expr.captures("abc").unwrap()[1..],
+        [
+            Some("abc".to_string()),
+            Some("a".to_string()),
+            Some("bc".to_string()),
+            Some("c".to_string())
+        ]
+    );
+    assert!(Regexp::new("([)])").unwrap().matches(")"));
+    assert!(Regexp::new("([^(])+").unwrap().matches("ab"));
+    assert!(Regexp::new("(\\))(\\()").unwrap().matches(")("));
+}
+
+#[test]
+fn semantically_eq() {
+    let eq = |a, b| {
+        Regexp::new(a)
+            .unwrap()
+            .semantically_eq(&Regexp::new(b).unwrap())
+    };
+
+    assert_ne!(Regexp::new("aa").unwrap(), Regexp::new("a{2}").unwrap());
+    assert!(eq("aa", "a{2}"));
+    assert!(eq("xa{3}y", "xaaay"));
+    assert!(eq("a{1}", "a"));
+    assert!(eq("ab{0}c", "ac"));
+    assert!(eq("a{0,}b{1,}c{0,1}", "a*b+c?"));
+    assert!(eq("(?:ab)c", "abc"));
+    assert!(eq("(?:ab){2}", "abab"));
+    assert!(eq("(x{2}|y)", "(xx|y)"));
+    assert!(eq("(?i)(?:ab)", "(?i)ab"));
+
+    assert!(!eq("(?i:a)b", "ab"));
+    assert!(!eq("(a)", "a"));
+    assert!(!eq("a{2,3}", "aa"));
+    assert!(!eq("a|b", "[ab]"));
+}
+
+#[test]
+fn simplify() {
+    let expr = Regexp::new("(?:a){1}(?:b)*b*x{0}c{1}(?:(d))(?:e|f)(?:g+)").unwrap();
+    let simplified = expr.simplify();
+
+    assert_eq!(simplified.to_string(), "ab*c(d)(?:e|f)g+");
+    assert_eq!(simplified, Regexp::new("ab*c(d)(?:e|f)g+").unwrap());
+
+    for text in ["acdeg", "abbbcdfgg", "acdg", "abcdegx"] {
+        assert_eq!(simplified.matches(text), expr.matches(text));
+        assert_eq!(simplified.captures(text), expr.captures(text));
+    }
+
+    assert_eq!(
+        Regexp::new("(?:a*)*").unwrap().simplify().to_string(),
+        "(?:a*)*"
+    );
+    assert_eq!(
+        Regexp::new("(a){0}\\1").unwrap().simplify().to_string(),
+        "(a){0}\\1"
+    );
+    assert_eq!(
+        Regexp::new("(?i:a)b").unwrap().simplify().to_string(),
+        "(?i:a)b"
+    );
+    assert_eq!(
+        Regexp::new("a*?a*?a*").unwrap().simplify().to_string(),
+        "a*?a*"
+    );
+    assert_eq!(
+        Regexp::new("x(?:(?:y))").unwrap().simplify(),
+        Regexp::literal("xy")
+    );
+}
+
+#[test]
+fn shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Regexp>();
+
+    let expr = Arc::new(Regexp::new("(a|b)+c").unwrap());
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let expr = Arc::clone(&expr);
+            std::thread::spawn(move || expr.matches(&("ab".repeat(i) + "c")))
+        })
+        .collect();
+    let results: Vec<bool> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+
+    assert_eq!(results, [false, true, true, true]);
+
+    let clone = Regexp::clone(&expr);
+    assert!(Arc::ptr_eq(&clone.tokens, &expr.tokens));
+}
+
+#[test]
+fn lazy_ranges() {
+    let greedy = Regexp::new("(a{1,3})(a*)").unwrap();
+    let lazy = Regexp::new("(a{1,3}?)(a*)").unwrap();
+
+    assert_eq!(greedy.captures("aaa").unwrap()[1], Some("aaa".to_string()));
+    assert_eq!(lazy.captures("aaa").unwrap()[1], Some("a".to_string()));
+
+    let forced = Regexp::new("(a{1,3}?)(aa)$").unwrap();
+    assert_eq!(forced.captures("aaa").unwrap()[1], Some("a".to_string()));
+
+    assert_eq!(Regexp::new("a{2,5}?").unwrap().find("aaaaaa"), Some((0, 2)));
+    assert_eq!(Regexp::new("a{2,}?").unwrap().find("aaaa"), Some((0, 2)));
+    assert_eq!(Regexp::new("a{2}?").unwrap().find("aaaa"), Some((0, 2)));
+    assert!(Regexp::new("a{1,3}?b").unwrap().matches("aaab"));
+    assert_eq!(Regexp::new("a{1,3}?").unwrap().to_string(), "a{1,3}?");
+    assert_eq!(
+        Regexp::new("a{1,3}??"),
+        Err(RegexpParsingError::RedundantQuantifier { index: 7 })
+    );
+}
+
+#[test]
+fn match_prefix() {
+    let expr = Regexp::new("abc").unwrap();
+
+    assert_eq!(
+        expr.match_prefix("abx"),
+        PrefixMatch::Failed { consumed: 2 }
+    );
+    assert_eq!(
+        expr.match_prefix("xbc"),
+        PrefixMatch::Failed { consumed: 0 }
+    );
+    assert_eq!(expr.match_prefix("ab"), PrefixMatch::Failed { consumed: 2 });
+    assert_eq!(expr.match_prefix("abcd"), PrefixMatch::Matched(3));
+
+    let expr = Regexp::new("\\d+(px|em)").unwrap();
+
+    assert_eq!(
+        expr.match_prefix("12pt"),
+        PrefixMatch::Failed { consumed: 3 }
+    );
+    assert_eq!(expr.match_prefix("12em"), PrefixMatch::Matched(4));
+    assert_eq!(
+        Regexp::new("a|abcd").unwrap().match_prefix("xyz"),
+        PrefixMatch::Failed { consumed: 0 }
+    );
+    assert_eq!(
+        Regexp::new("(a|abcd)$").unwrap().match_prefix("abcx"),
+        PrefixMatch::Failed { consumed: 3 }
+    );
+    assert_eq!(
+        Regexp::new("x*").unwrap().match_prefix("y"),
+        PrefixMatch::Matched(0)
+    );
+}
+
+#[test]
+fn new_collect_errors() {
+    assert_eq!(
+        Regexp::new_collect_errors("(a[b"),
+        Err(vec![
+            RegexpParsingError::UnclosedParen { index: 0 },
+            RegexpParsingError::UnterminatedClass { index: 2 },
+        ])
+    );
+    assert_eq!(
+        Regexp::new_collect_errors("*a(b(c\\"),
+        Err(vec![
+            RegexpParsingError::DanglingQuantifier { index: 0 },
+            RegexpParsingError::UnclosedParen { index: 2 },
+            RegexpParsingError::UnclosedParen { index: 4 },
+            RegexpParsingError::TrailingBackslash { index: 6 },
+        ])
+    );
+    assert_eq!(
+        Regexp::new_collect_errors("(?<x>a)(?<x>b)[z-a]c)"),
+        Err(vec![
+            RegexpParsingError::DuplicateGroupName { index: 7 },
+            RegexpParsingError::InvalidClassRange { index: 15 },
+            RegexpParsingError::UnopenedParen { index: 20 },
+        ])
+    );
+    assert_eq!(
+        Regexp::new_collect_errors("[}-a]"),
+        Err(vec![RegexpParsingError::InvalidClassRange { index: 1 }])
+    );
+    assert_eq!(
+        Regexp::new_collect_errors("[,{1,2}-("),
+        Err(vec![
+            RegexpParsingError::InvalidClassRange { index: 6 },
+            RegexpParsingError::UnterminatedClass { index: 0 },
+        ])
+    );
+    assert_eq!(
+        Regexp::new("[\\x{7a}-a]"),
+        Err(RegexpParsingError::InvalidClassRange { index: 1 })
+    );
+    assert_eq!(
+        Regexp::new_collect_errors("a(b)c").unwrap(),
+        Regexp::new("a(b)c").unwrap()
+    );
+}
+
+#[test]
+fn empty_pattern() {
+    let expr = Regexp::new("").unwrap();
+
+    assert!(expr.matches(""));
+    assert!(!expr.matches("abc"));
+    assert!(expr.is_match(""));
+    assert!(expr.is_match("abc"));
+    assert_eq!(expr.find("abc"), Some((0, 0)));
+    assert_eq!(expr.find(""), Some((0, 0)));
+    assert_eq!(
+        expr.find_iter("ab").collect::<Vec<_>>(),
+        [(0, 0), (1, 1), (2, 2)]
+    );
+    assert_eq!(expr.count("ab"), 3);
+    assert_eq!(expr.shortest_match("abc"), Some(0));
+    assert_eq!(expr.replace_all("ab", "-"), "-a-b-");
+}
+
+#[test]
+fn nest_limit() {
+    let nested = |depth| "(".repeat(depth) + "a" + &")".repeat(depth);
+
+    assert!(Regexp::new(&nested(100)).unwrap().matches("a"));
+    assert_eq!(
+        Regexp::new(&nested(101)),
+        Err(RegexpParsingError::TooDeeplyNested { index: 100 })
+    );
+    assert_eq!(
+        Regexp::new(&nested(10_000)),
+        Err(RegexpParsingError::TooDeeplyNested { index: 100 })
+    );
+
+    let mut builder = RegexpBuilder::new();
+    builder.nest_limit(2);
+
+    assert!(builder.build("((a)(?:b))(c)").is_ok());
+    assert_eq!(
+        builder.build("x(?:(?=(a)))"),
+        Err(RegexpParsingError::TooDeeplyNested { index: 7 })
+    );
+    assert!(builder.nest_limit(0).build("a(?i)b").is_ok());
+}
+
+#[test]
+fn long_inputs() {
+    let text = "a".repeat(100_000) + "b";
+    let captures = Regexp::new("(a+)(b)")
+        .unwrap()
+        .capture_ranges(&text)
+        .unwrap();
+
+    assert_eq!(
+        captures,
+        vec![Some(0..100_001), Some(0..100_000), Some(100_000..100_001)]
+    );
+    assert_eq!(
+        Regexp::new(r"(a)\1*").unwrap().find(&text),
+        Some((0, 100_000))
+    );
+    assert_eq!(
+        Regexp::new("(?:a|b)*?b").unwrap().find(&text),
+        Some((0, 100_001))
+    );
+    assert_eq!(
+        Regexp::new("(?>a*)b").unwrap().find(&text),
+        Some((0, 100_001))
+    );
+}
+
+#[test]
+fn size_limit() {
+    assert_eq!(
+        Regexp::new("a{1000000}"),
+        Err(RegexpParsingError::TooLarge { index: 1 })
+    );
+    assert_eq!(
+        Regexp::new("(a{1000}){1000}"),
+        Err(RegexpParsingError::TooLarge { index: 9 })
+    );
+    assert_eq!(
+        Regexp::new("x(?:a{1000000})"),
+        Err(RegexpParsingError::TooLarge { index: 5 })
+    );
+    assert!(Regexp::new("a{1000}").unwrap().matches(&"a".repeat(1000)));
+
+    let mut builder = RegexpBuilder::new();
+    builder.size_limit(1000);
+
+    assert!(builder.build("abc|d+").is_ok());
+    assert!(builder.build("[a-z]{8}").is_ok());
+    assert_eq!(
+        builder.build("[a-z]{100}"),
+        Err(RegexpParsingError::TooLarge { index: 5 })
+    );
+    assert_eq!(
+        builder.build("a{6}b{6}"),
+        Err(RegexpParsingError::TooLarge { index: 5 })
+    );
+    // Classes count their ranges, and lookaheads their expression.
+    assert_eq!(
+        builder.build("[acegikmoqsuwy]{8}"),
+        Err(RegexpParsingError::TooLarge { index: 15 })
+    );
+    assert_eq!(
+        builder.build("(?=abcdefghij){4}"),
+        Err(RegexpParsingError::TooLarge { index: 14 })
+    );
+    assert!(builder
+        .size_limit(usize::MAX)
+        .build("(a{100000}){100000}")
+        .is_ok());
+}
+
+#[test]
+fn is_match_reader() {
+    use std::io::Cursor;
+
+    // Long enough to be read in several chunks, with an `é` split by the
+    // first chunk boundary.
+    let text = "x".repeat(8191) + "é error: 42 " + &"y".repeat(10_000) + "\nlast";
+    let reader = || Cursor::new(text.as_bytes());
+
+    for pattern in [
+        "error: \\d+",
+        "error: \\d{3}",
+        "^x",
+        "^é",
+        "(?m)^last$",
+        "(?m)^y",
+        "xé",
+        "\\bx",
+        "\\by",
+        "t$",
+        "x$",
+        "é +e",
+        "y(?=\\n)",
+        "z",
+    ] {
+        let expr = Regexp::new(pattern).unwrap();
+        assert_eq!(
+            expr.is_match_reader(reader()).unwrap(),
+            expr.is_match(&text)
+        );
+    }
+
+    assert!(Regexp::new("")
+        .unwrap()
+        .is_match_reader(Cursor::new(""))
+        .unwrap());
+    assert_eq!(
+        Regexp::new("a")
+            .unwrap()
+            .is_match_reader(Cursor::new(b"a\xff"))
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn literal_prefix() {
+    let prefix = |pattern| Regexp::new(pattern).unwrap().literal_prefix();
+
+    assert_eq!(prefix("error:"), ("error:".chars().collect(), true));
+    assert_eq!(prefix("^ab(c)d+e"), ("abcd".chars().collect(), false));
+    assert_eq!(prefix("\\bab(?=x)c"), ("abc".chars().collect(), true));
+    assert_eq!(prefix("(?:ab|ac)d"), (Vec::new(), false));
+    assert_eq!(prefix("(?i)ab"), (Vec::new(), false));
+    assert_eq!(prefix("a(?i:b)c"), (vec!['a'], false));
+    assert_eq!(prefix("a?b"), (Vec::new(), false));
+
+    let text: Vec<char> = "warn: x\nerror: disk\nerror:\nok error: net"
+        .chars()
+        .collect();
+
+    for pattern in [
+        "error:",
+        "error: \\w+",
+        "^error",
+        "(?m)^error",
+        "r+o",
+        "\\bo",
+        "x|ok",
+        "",
+    ] {
+        let expr = Regexp::new(pattern).unwrap();
+
+        for start in 0..=text.len() {
+            let naive = (start..=text.len()).find_map(|i| expr.captures_anchored(&text, i));
+            assert_eq!(expr.captures_at(&text, start), naive);
+        }
+    }
+}
+
+#[test]
+fn leading_quantifiers() {
+    assert_eq!(
+        Regexp::new("+a"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 0 })
+    );
+    assert_eq!(
+        Regexp::new("?a"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 0 })
+    );
+    assert_eq!(
+        Regexp::new("x|+a"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 2 })
+    );
+    assert_eq!(
+        Regexp::new("(?:?a)"),
+        Err(RegexpParsingError::DanglingQuantifier { index: 3 })
+    );
+
+    assert!(Regexp::new("\\+a").unwrap().matches("+a"));
+    assert!(Regexp::new("\\?a").unwrap().matches("?a"));
+}
+
+#[test]
+fn split_iter() {
+    let expr = Regexp::new("\\d").unwrap();
+
+    assert_eq!(
+        expr.split_iter("a1b2c").collect::<Vec<_>>(),
+        ["a", "b", "c"]
+    );
+    assert_eq!(expr.split_iter("1é2").collect::<Vec<_>>(), ["", "é", ""]);
+    assert_eq!(expr.split_iter("").collect::<Vec<_>>(), [""]);
+    assert_eq!(
+        Regexp::new("x*")
+            .unwrap()
+            .split_iter("ñb")
+            .collect::<Vec<_>>(),
+        ["", "ñ", "b", ""]
+    );
+
+    let mut pieces = expr.split_iter("a1b2c");
+    assert_eq!(pieces.next(), Some("a"));
+    assert_eq!(pieces.next(), Some("b"));
+    assert_eq!(pieces.next(), Some("c"));
+    assert_eq!(pieces.next(), None);
+    assert_eq!(pieces.next(), None);
+}
+
+#[test]
+fn matches_char() {
+    let expr = Regexp::new("[a-z]").unwrap();
+
+    assert!(expr.matches_char('q'));
+    assert!(!expr.matches_char('5'));
+    assert!(!Regexp::new("[a-z]{2}").unwrap().matches_char('q'));
+    assert!(Regexp::new("\\d|é").unwrap().matches_char('é'));
+    assert!(Regexp::new_case_insensitive("q").unwrap().matches_char('Q'));
+    assert!("a1b2"
+        .chars()
+        .filter(|&chr| expr.matches_char(chr))
+        .eq(['a', 'b']));
+}
+
+#[test]
+fn quantified_anchors() {
+    assert_eq!(
+        Regexp::new("^*a"),
+        Err(RegexpParsingError::QuantifiedAnchor { index: 1 })
+    );
+    assert_eq!(
+        Regexp::new("a$?"),
+        Err(RegexpParsingError::QuantifiedAnchor { index: 2 })
+    );
+    assert_eq!(
+        Regexp::new("(a\\b+)"),
+        Err(RegexpParsingError::QuantifiedAnchor { index: 4 })
+    );
+    assert_eq!(
+        Regexp::new("\\A{2}"),
+        Err(RegexpParsingError::QuantifiedAnchor { index: 2 })
+    );
+
+    assert!(Regexp::new("\\^*").unwrap().matches("^^"));
+    assert!(Regexp::new("$\\*").is_ok());
+    assert!(Regexp::new("^{x}").unwrap().matches("{x}"));
+    assert!(Regexp::new("(?:^)*a").is_ok());
+}
+
+#[test]
+fn engines_agree() {
+    let patterns = [
+        "a*b*c*",
+        "(a|ab)(c|bcd)",
+        "x+é",
+        "^\\d{2,4}$",
+        "\\bfoo\\b",
+        "(?i)hello|world",
+        "[^a-c]+?z",
+        "a{3}|b?",
+        "[ab]+?b*?",
+        "(?=ab)a",
+        "",
+    ];
+    let texts = [
+        "", "abc", "abcd", "xxé", "1234", "12345", "a foo b", "HeLLo", "dz", "aaa", "ab", "xy",
+        "zy",
+    ];
+
+    for pattern in patterns {
+        let expr = Regexp::new(pattern).unwrap();
+        let nfa = expr.compile().unwrap();
+
+        for text in texts {
+            let chars: Vec<char> = text.chars().collect();
+            let mut state = expr.new_state(&chars);
+            let matches = expr.start_match(&mut state, 0, &mut |_, end| end == chars.len());
+
+            assert_eq!(nfa.matches(&chars), matches, "{pattern} {text}");
+            assert_eq!(
+                nfa.is_match(&chars),
+                expr.find_at(&chars, 0).is_some(),
+                "{pattern} {text}"
+            );
+
+            if nfa.finds_spans() {
+                assert_eq!(
+                    nfa.find(&chars),
+                    expr.find_at(&chars, 0),
+                    "{pattern} {text}"
+                );
+            }
+        }
+    }
+
+    assert!(!Regexp::new("(a*)*")
+        .unwrap()
+        .compile()
+        .unwrap()
+        .finds_spans());
+    assert!(!Regexp::new("(?:a??)?")
+        .unwrap()
+        .compile()
+        .unwrap()
+        .finds_spans());
+
+    assert!(Regexp::new("(a)\\1").unwrap().compile().is_none());
+    assert!(Regexp::new("(a)\\1").unwrap().is_match("xaa"));
+    assert!(Regexp::new("a++").unwrap().compile().is_none());
+
+    for (pattern, matched) in [
+        ("([ab])(?=\\1).", Some((0, 2))),
+        ("(a)(?=\\1).", Some((0, 2))),
+        ("(a)(?!\\1).", None),
+    ] {
+        let expr = Regexp::new(pattern).unwrap();
+
+        assert!(expr.compile().is_none(), "{pattern}");
+        assert_eq!(expr.find("aa"), matched, "{pattern}");
+        assert_eq!(expr.find_iter("aa").next(), matched, "{pattern}");
+        assert_eq!(expr.matches("aa"), matched.is_some(), "{pattern}");
+        assert_eq!(expr.is_match("aa"), matched.is_some(), "{pattern}");
+        assert_eq!(expr.matches_at("aa", 0), matched.map(|_| 2), "{pattern}");
+    }
+}
+
+#[test]
+fn capture_spans() {
+    let expr = Regexp::new("(\\d+)x(\\d+)").unwrap();
+    let text = "size: 1920x1080";
+    let spans = expr.capture_spans(text).unwrap();
+
+    assert_eq!(spans, [Some((6, 15)), Some((6, 10)), Some((11, 15))]);
+    assert_eq!(&text[6..10], "1920");
+    assert_eq!(&text[11..15], "1080");
+    assert_eq!(
+        expr.capture_spans("é 3x4").unwrap()[2].map(|(start, end)| &"é 3x4"[start..end]),
+        Some("4")
+    );
+    assert_eq!(expr.capture_spans("é 3x4").unwrap()[2], Some((5, 6)));
+    assert_eq!(expr.capture_spans("é 3x4").unwrap()[0], Some((3, 6)));
+    assert_eq!(expr.find("é 3x4"), Some((2, 5)));
+    assert_eq!(
+        Regexp::new("(a)|(b)").unwrap().capture_spans("b"),
+        Some(vec![Some((0, 1)), None, Some((0, 1))])
+    );
+    assert_eq!(expr.capture_spans("x"), None);
 }
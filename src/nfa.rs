@@ -0,0 +1,383 @@
+use crate::{match_value_at_index, Atom, Flags, Greed, Regexp, State, Token};
+
+/// A Thompson NFA compiled from the tokens of a [`Regexp`]. Simulating it
+/// keeps a set of active states per position instead of backtracking, so a
+/// match takes O(n·m) time for an input of n chars and m states. Possessive
+/// quantifiers and atomic groups depend on backtracking order, and
+/// backreferences on what was captured, so they can't be compiled. That
+/// includes backreferences in lookaheads, which the NFA matches as a whole
+/// without the captures before them.
+#[derive(Debug)]
+pub(crate) struct Nfa {
+    insts: Vec<Inst>,
+    group_count: usize,
+    /// Whether some repeated atom can match the empty string, see
+    /// [`Nfa::finds_spans`].
+    empty_repeats: bool,
+}
+
+#[derive(Debug)]
+enum Inst {
+    /// Matches a single atom that is either zero-width or consumes one char.
+    Atom(Atom, Flags),
+    /// Continues at both targets, preferring the first.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+impl Inst {
+    /// Splits between one more repetition at `again` and leaving at `done`,
+    /// preferring the repetition unless `lazy`.
+    fn repeat(again: usize, done: usize, lazy: bool) -> Self {
+        match lazy {
+            true => Self::Split(done, again),
+            false => Self::Split(again, done),
+        }
+    }
+}
+
+impl Nfa {
+    pub(crate) fn new(regexp: &Regexp) -> Option<Self> {
+        let mut nfa = Self {
+            insts: Vec::new(),
+            group_count: regexp.group_count(),
+            empty_repeats: false,
+        };
+
+        nfa.compile_tokens(&regexp.tokens, regexp.flags)?;
+        nfa.insts.push(Inst::Match);
+        Some(nfa)
+    }
+
+    /// Returns how many bytes the instructions compiled from `regexp` take,
+    /// without compiling them.
+    pub(crate) fn size_of(regexp: &Regexp) -> usize {
+        tokens_size(&regexp.tokens)
+    }
+
+    /// Returns whether the whole of `chars` is matched.
+    pub(crate) fn matches(&self, chars: &[char]) -> bool {
+        self.match_end(chars, false, |end| end == chars.len())
+            .is_some()
+    }
+
+    /// Returns whether a match starts at any index of `chars`.
+    pub(crate) fn is_match(&self, chars: &[char]) -> bool {
+        self.match_end(chars, true, |_| true).is_some()
+    }
+
+    /// Returns the end of the shortest match starting at the first char.
+    pub(crate) fn shortest_match(&self, chars: &[char]) -> Option<usize> {
+        self.match_end(chars, false, |_| true)
+    }
+
+    /// Returns whether [`Nfa::find`] finds the same spans as backtracking.
+    /// Backtracking rejects repetitions that match the empty string, which
+    /// threads can't tell apart from the ones that don't, so patterns where
+    /// a repeated atom can match it are left to backtracking.
+    pub(crate) fn finds_spans(&self) -> bool {
+        !self.empty_repeats
+    }
+
+    /// Returns the span of the leftmost match, ending where backtracking
+    /// would first succeed. Threads are kept in the order backtracking would
+    /// try them, so once one matches, the later ones can be dropped.
+    pub(crate) fn find(&self, chars: &[char]) -> Option<(usize, usize)> {
+        let mut state = State::new(chars, self.group_count);
+        let mut seen = vec![usize::MAX; self.insts.len()];
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        let mut stack = Vec::new();
+        let mut found = None;
+
+        for index in 0..=chars.len() {
+            if found.is_none() {
+                self.add(
+                    &mut state,
+                    &mut stack,
+                    &mut current,
+                    &mut seen,
+                    (0, index),
+                    index,
+                );
+            }
+
+            next.clear();
+
+            for &(pc, start) in &current {
+                match &self.insts[pc] {
+                    Inst::Match => {
+                        found = Some((start, index));
+                        break;
+                    },
+                    Inst::Atom(value, flags) => {
+                        let mut end = None;
+                        match_value_at_index(*flags, &mut state, index, value, &mut |_, i| {
+                            end = Some(i);
+                            true
+                        });
+
+                        if end == Some(index + 1) {
+                            self.add(
+                                &mut state,
+                                &mut stack,
+                                &mut next,
+                                &mut seen,
+                                (pc + 1, start),
+                                index + 1,
+                            );
+                        }
+                    },
+                    _ => {},
+                }
+            }
+
+            if next.is_empty() && found.is_some() {
+                break;
+            }
+
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        found
+    }
+
+    /// Returns the first index where a match starting at the first char ends
+    /// and `accept` agrees. If `unanchored`, matches may start at any index.
+    fn match_end(
+        &self,
+        chars: &[char],
+        unanchored: bool,
+        accept: impl Fn(usize) -> bool,
+    ) -> Option<usize> {
+        let mut state = State::new(chars, self.group_count);
+        let mut seen = vec![usize::MAX; self.insts.len()];
+        let mut current = Vec::new();
+        // Reused for every index, so matching doesn't allocate per char.
+        let mut next = Vec::new();
+        let mut stack = Vec::new();
+
+        self.add(&mut state, &mut stack, &mut current, &mut seen, (0, 0), 0);
+
+        for index in 0..=chars.len() {
+            if unanchored && index > 0 {
+                self.add(
+                    &mut state,
+                    &mut stack,
+                    &mut current,
+                    &mut seen,
+                    (0, index),
+                    index,
+                );
+            }
+
+            next.clear();
+
+            for &(pc, _) in &current {
+                match &self.insts[pc] {
+                    Inst::Match if accept(index) => return Some(index),
+                    Inst::Atom(value, flags) => {
+                        let mut end = None;
+                        match_value_at_index(*flags, &mut state, index, value, &mut |_, i| {
+                            end = Some(i);
+                            true
+                        });
+
+                        if end == Some(index + 1) {
+                            self.add(
+                                &mut state,
+                                &mut stack,
+                                &mut next,
+                                &mut seen,
+                                (pc + 1, 0),
+                                index + 1,
+                            );
+                        }
+                    },
+                    _ => {},
+                }
+            }
+
+            if next.is_empty() && !unanchored {
+                return None;
+            }
+
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        None
+    }
+
+    /// Adds the thread at `pc` of a match that started at `start`, and the
+    /// ones reachable from it without consuming a char, to `list` in the
+    /// order of preference. Zero-width atoms are evaluated at `index` on
+    /// the way. `stack` is only a buffer for the states left to visit.
+    fn add<'a>(
+        &'a self,
+        state: &mut State<'a>,
+        stack: &mut Vec<usize>,
+        list: &mut Vec<(usize, usize)>,
+        seen: &mut [usize],
+        (pc, start): (usize, usize),
+        index: usize,
+    ) {
+        stack.push(pc);
+
+        while let Some(pc) = stack.pop() {
+            if seen[pc] == index {
+                continue;
+            }
+
+            seen[pc] = index;
+
+            match &self.insts[pc] {
+                Inst::Atom(Atom::Char(_) | Atom::Class(_) | Atom::Wildcard, _) | Inst::Match => {
+                    list.push((pc, start))
+                },
+                Inst::Atom(value, flags) => {
+                    if match_value_at_index(*flags, state, index, value, &mut |_, _| true) {
+                        stack.push(pc + 1);
+                    }
+                },
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                },
+                Inst::Jump(target) => stack.push(*target),
+            }
+        }
+    }
+
+    fn push(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    fn compile_tokens(&mut self, tokens: &[Token], flags: Flags) -> Option<()> {
+        for (value, quantifier) in tokens {
+            if quantifier.greed() == Greed::Possessive {
+                return None;
+            }
+
+            let (min, max) = quantifier.bounds();
+            let lazy = quantifier.greed() == Greed::Lazy;
+
+            if (min, max) != (1, Some(1)) && value.len_bounds().0 == 0 {
+                self.empty_repeats = true;
+            }
+
+            for _ in 0..min {
+                self.compile_atom(value, flags)?;
+            }
+
+            match max {
+                None => {
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile_atom(value, flags)?;
+                    self.push(Inst::Jump(split));
+                    self.insts[split] = Inst::repeat(split + 1, self.insts.len(), lazy);
+                },
+                Some(max) => {
+                    let splits = (min..max)
+                        .map(|_| {
+                            let split = self.push(Inst::Split(0, 0));
+                            self.compile_atom(value, flags)?;
+                            Some(split)
+                        })
+                        .collect::<Option<Vec<usize>>>()?;
+                    let end = self.insts.len();
+
+                    for split in splits {
+                        self.insts[split] = Inst::repeat(split + 1, end, lazy);
+                    }
+                },
+            }
+        }
+
+        Some(())
+    }
+
+    fn compile_atom(&mut self, value: &Atom, flags: Flags) -> Option<()> {
+        match value {
+            Atom::Expr(expr) | Atom::Group(_, _, expr) => {
+                self.compile_tokens(&expr.tokens, expr.flags)?
+            },
+            Atom::AtomicGroup(_) | Atom::Backref(_) => return None,
+            Atom::Lookahead(expr) | Atom::NegLookahead(expr) if expr.has_backrefs() => return None,
+            Atom::Literal(chars) => {
+                for &chr in chars {
+                    self.push(Inst::Atom(Atom::Char(chr), flags));
+                }
+            },
+            Atom::Alternation(branches) => {
+                let mut jumps = Vec::new();
+
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 == branches.len() {
+                        self.compile_tokens(&branch.tokens, branch.flags)?;
+                        break;
+                    }
+
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile_tokens(&branch.tokens, branch.flags)?;
+                    jumps.push(self.push(Inst::Jump(0)));
+                    self.insts[split] = Inst::Split(split + 1, self.insts.len());
+                }
+
+                let end = self.insts.len();
+
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jump(end);
+                }
+            },
+            value => {
+                self.push(Inst::Atom(value.clone(), flags));
+            },
+        }
+
+        Some(())
+    }
+}
+
+/// Returns how many bytes the instructions `tokens` compile to take, counting
+/// what the atoms they hold allocate, like the ranges of a class.
+pub(crate) fn tokens_size(tokens: &[Token]) -> usize {
+    tokens
+        .iter()
+        .map(|(value, quantifier)| {
+            // Every optional copy is preceded by a split, and an unbounded
+            // repetition adds a split and a jump around one more copy.
+            let (copies, splits) = match quantifier.bounds() {
+                (min, Some(max)) => (max, max - min),
+                (min, None) => (min.saturating_add(1), 2),
+            };
+
+            atom_size(value)
+                .saturating_mul(copies)
+                .saturating_add(splits.saturating_mul(INST_SIZE))
+        })
+        .fold(0, usize::saturating_add)
+}
+
+const INST_SIZE: usize = std::mem::size_of::<Inst>();
+
+/// Returns how many bytes one copy of `value` compiles to. A lookahead also
+/// counts its expression, which is matched again at every index it is tried.
+fn atom_size(value: &Atom) -> usize {
+    match value {
+        Atom::Expr(expr) | Atom::Group(_, _, expr) | Atom::AtomicGroup(expr) => {
+            tokens_size(&expr.tokens)
+        },
+        Atom::Literal(chars) => chars.len().saturating_mul(INST_SIZE),
+        Atom::Alternation(branches) => branches
+            .iter()
+            .map(|branch| tokens_size(&branch.tokens).saturating_add(2 * INST_SIZE))
+            .fold(0, usize::saturating_add),
+        Atom::Class(class) => INST_SIZE + class.ranges.len() * std::mem::size_of::<(char, char)>(),
+        Atom::Lookahead(expr) | Atom::NegLookahead(expr) => INST_SIZE
+            .saturating_add(expr.pattern.len())
+            .saturating_add(tokens_size(&expr.tokens)),
+        _ => INST_SIZE,
+    }
+}